@@ -1,300 +1,834 @@
 //#![allow(dead_code)]
 #![allow(unused_must_use)]
 
-use bindgen;
-use std::{collections::HashMap, process::Command};
+use std::{path::Path, process::Command};
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-check-cfg=cfg(libmagic_vendored)");
     let out_dir = std::env::var_os("OUT_DIR")
         .unwrap()
         .to_string_lossy()
         .to_string();
 
-    if let Ok(target) = std::env::var("TARGET") {
-        if target.starts_with("x86_64-unknown-linux-musl")
-            || target.starts_with("aarch64-unknown-linux-musl")
-        {
-            build_and_statically_link_linux(&out_dir);
-        } else if target.starts_with("x86_64-pc-windows-gnu") {
-            // cross-compile linux to windows
-            build_and_statically_link_windows(&out_dir);
-        } else if target.starts_with("aarch64-apple-darwin")
-            || target.starts_with("x86_64-apple-darwin")
-        {
-            // cross-compile linux to macos
-            build_and_statically_link_macos();
-        } else {
-            build_and_statically_link_linux(&out_dir)
+    let system_version = match use_system_libmagic() {
+        true => Some(link_system(&out_dir)),
+        _ => {
+            build_vendored(&out_dir);
+            None
         }
+    };
+
+    if std::env::var_os("CARGO_FEATURE_BUNDLED_DATABASE").is_some() {
+        bundle_database(&out_dir, system_version);
     }
 }
 
-fn revert_patches() {
-    Command::new("/usr/bin/patch")
-        .current_dir("./")
-        .arg("-R")
-        .arg("-u")
-        .arg("file/Makefile.am")
-        .arg("-i")
-        .arg("patches/file_Makefile_am.patch")
-        .status();
-
-    Command::new("/usr/bin/patch")
-        .current_dir("./")
-        .arg("-R")
-        .arg("-u")
-        .arg("file/configure.ac")
-        .arg("-i")
-        .arg("patches/file_configure_ac.patch")
-        .status();
+/// Whether to link an installed libmagic instead of building the vendored `file` submodule
+///
+/// Enabled by the `system` feature or a non-empty `LIBMAGIC_NO_VENDOR` other than `0`.
+fn use_system_libmagic() -> bool {
+    println!("cargo:rerun-if-env-changed=LIBMAGIC_NO_VENDOR");
+    let no_vendor = std::env::var("LIBMAGIC_NO_VENDOR").unwrap_or_default();
+    std::env::var_os("CARGO_FEATURE_SYSTEM").is_some()
+        || !(no_vendor.is_empty() || no_vendor == "0")
 }
 
-fn apply_patches() {
-    Command::new("/usr/bin/patch")
-        .current_dir("./")
-        .arg("-N")
-        .arg("-u")
-        .arg("file/Makefile.am")
-        .arg("-i")
-        .arg("patches/file_Makefile_am.patch")
-        .status();
-
-    Command::new("/usr/bin/patch")
-        .current_dir("./")
-        .arg("-N")
-        .arg("-u")
-        .arg("file/configure.ac")
-        .arg("-i")
-        .arg("patches/file_configure_ac.patch")
-        .status();
+/// Links an installed libmagic and provides the bindings matching its `magic.h`
+///
+/// `LIBMAGIC_LIB_DIR` (and optionally `LIBMAGIC_INCLUDE_DIR`) take precedence over pkg-config.
+/// Linking is dynamic unless `LIBMAGIC_STATIC` is set, which pkg-config honors as well,
+/// pulling in the compression libraries a static libmagic depends on.
+/// With `LIBMAGIC_LIB_DIR` those come from `LIBMAGIC_LIBS`, see [`static_dependencies()`].
+/// Either way they tell which codecs the system libmagic was built with.
+/// Returns the `MAGIC_VERSION` of its `magic.h`.
+fn link_system(out_dir: &str) -> u32 {
+    for var in [
+        "LIBMAGIC_LIB_DIR",
+        "LIBMAGIC_INCLUDE_DIR",
+        "LIBMAGIC_STATIC",
+        "LIBMAGIC_LIBS",
+    ] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+
+    let (include_paths, dependencies) = if let Some(lib_dir) = std::env::var_os("LIBMAGIC_LIB_DIR")
+    {
+        let kind = match std::env::var_os("LIBMAGIC_STATIC") {
+            Some(_) => "static",
+            None => "dylib",
+        };
+        println!(
+            "cargo:rustc-link-search=native={}",
+            lib_dir.to_string_lossy()
+        );
+        println!("cargo:rustc-link-lib={}=magic", kind);
+        let dependencies = static_dependencies(Path::new(&lib_dir));
+        if kind == "static" {
+            if dependencies.is_none() {
+                println!(
+                    "cargo:warning=set LIBMAGIC_LIBS to the compression libraries \
+                     the static libmagic depends on if linking fails"
+                );
+            }
+            for lib in dependencies.iter().flatten() {
+                println!("cargo:rustc-link-lib={}", lib);
+            }
+        }
+        let include_paths = std::env::var_os("LIBMAGIC_INCLUDE_DIR")
+            .map(std::path::PathBuf::from)
+            .into_iter()
+            .collect();
+        (include_paths, dependencies.unwrap_or_default())
+    } else {
+        let include_paths = pkg_config::Config::new()
+            .probe("libmagic")
+            .unwrap_or_else(|err| {
+                panic!(
+                    "could not find the system libmagic via pkg-config: {}; \
+                     set LIBMAGIC_LIB_DIR (and LIBMAGIC_INCLUDE_DIR) to point to it instead, \
+                     or build the vendored copy by disabling the `system` feature and unsetting LIBMAGIC_NO_VENDOR",
+                    err
+                )
+            })
+            .include_paths;
+        // `Libs.private`, whether linking statically or not
+        let dependencies = pkg_config::Config::new()
+            .statik(true)
+            .cargo_metadata(false)
+            .probe("libmagic")
+            .map(|lib| lib.libs)
+            .unwrap_or_default();
+        (include_paths, dependencies)
+    };
+
+    announce_codecs(CODECS.iter().filter_map(|(feature, _, lib, _)| {
+        dependencies
+            .iter()
+            .any(|dependency| dependency == lib)
+            .then_some(*feature)
+    }));
+
+    // pkg-config leaves out the compiler's default include paths
+    let header = include_paths
+        .iter()
+        .map(|dir| dir.as_path())
+        .chain([Path::new("/usr/local/include"), Path::new("/usr/include")])
+        .map(|dir| dir.join("magic.h"))
+        .find(|header| header.exists())
+        .unwrap_or_else(|| {
+            panic!("could not find `magic.h` of the system libmagic, set LIBMAGIC_INCLUDE_DIR")
+        });
+
+    let clang_args: Vec<String> = include_paths
+        .iter()
+        .map(|dir| format!("-I{}", dir.display()))
+        .collect();
+    bindings(out_dir, &header, &clang_args);
+    header_version(&header)
 }
 
-fn build_and_statically_link_linux(out_dir: &str) {
-    let install_path = format!("{}/build", out_dir);
-    std::fs::remove_dir_all(&install_path);
-    std::fs::create_dir(&install_path);
-
-    revert_patches();
-    // autoreconf -f -i
-    Command::new("/usr/bin/autoreconf")
-        .current_dir("file/")
-        .arg("-f")
-        .arg("-i")
-        .status()
-        .unwrap();
+/// Libraries a static libmagic in `lib_dir` depends on, i.e. the compression libraries it was built with
+///
+/// Taken from the whitespace-separated names in `LIBMAGIC_LIBS` (e.g. `z bz2 lzma zstd`) if set,
+/// otherwise from the `Libs.private` of the `libmagic.pc` that `make install` puts next to the library.
+/// `None` if neither is available.
+fn static_dependencies(lib_dir: &Path) -> Option<Vec<String>> {
+    if let Ok(libs) = std::env::var("LIBMAGIC_LIBS") {
+        return Some(libs.split_whitespace().map(str::to_string).collect());
+    }
+
+    let pc = lib_dir.join("pkgconfig").join("libmagic.pc");
+    println!("cargo:rerun-if-changed={}", pc.display());
+    let pc = std::fs::read_to_string(&pc).ok()?;
+    Some(
+        pc.lines()
+            .filter_map(|line| line.strip_prefix("Libs.private:"))
+            .flat_map(str::split_whitespace)
+            .filter_map(|flag| flag.strip_prefix("-l"))
+            .map(str::to_string)
+            .collect(),
+    )
+}
 
-    // make distclean
-    Command::new("/usr/bin/make")
-        .current_dir("file/")
-        .arg("distclean")
-        .status();
-    // ./configure --disable-silent-rules --enable-static=true --enable-shared=false --prefix=$(pwd)/build CC=x86_64-linux-musl-gcc
-    let mut configure_args: Vec<String> = Vec::from([
-        "./configure",
-        "--disable-silent-rules",
-        "--enable-static=yes",
-        "--enable-shared=no",
-        "--disable-zlib",
-        "--disable-bzlib",
-        "--disable-xzlib",
-        "--disable-zstdlib",
-        "--disable-lzlib",
-        &format!("--prefix={}", &install_path),
-    ])
-    .into_iter()
-    .map(|i| i.to_string())
-    .collect();
-
-    let rustc_target = std::env::var("TARGET").unwrap();
-
-    if rustc_target.starts_with("x86_64-unknown-linux-musl") {
-        configure_args.push(format!("CC={}", "x86_64-linux-musl-gcc"));
-    } else if rustc_target.starts_with("aarch64-unknown-linux-musl") {
-        configure_args.push(format!("CC={}", "aarch64-linux-musl-gcc"));
-        configure_args.push(format!("--host=aarch64-pc-linux-gnu"));
-    } else if rustc_target.starts_with("x86_64-unknown-linux-gnu") {
-        configure_args.push(format!("CC={}", "x86_64-linux-gnu-gcc"));
-        configure_args.push(format!("--host=x86_64-pc-linux-gnu"));
+/// Tells `cookie::Compression::supported()` which codecs libmagic was built with
+///
+/// Sets `LIBMAGIC_CODECS` for the crate to the lowercase cargo feature names of the codecs, separated by spaces.
+fn announce_codecs<'a>(features: impl IntoIterator<Item = &'a str>) {
+    let features: Vec<String> = features.into_iter().map(str::to_lowercase).collect();
+    println!("cargo:rustc-env=LIBMAGIC_CODECS={}", features.join(" "));
+}
+
+/// Places the compiled `Magdir` at `$OUT_DIR/magic.mgc` for the `bundled-database` feature
+///
+/// The database is compiled by `magic_compile`, which is built from the `file` submodule for the host,
+/// see [`build_magic_compile()`]. `$FILE_COMPILE` overrides it with a `file` binary of the same version.
+/// A system libmagic (`system_version`) has to be of that version as well to load the result.
+fn bundle_database(out_dir: &str, system_version: Option<u32>) {
+    let bundled = format!("{}/magic.mgc", out_dir);
+    let magdir = std::fs::canonicalize("file/magic/Magdir")
+        .expect("`bundled-database` needs the `file` submodule with `magic/Magdir`");
+    if let Some(system_version) = system_version {
+        let vendored = file_version();
+        assert!(
+            vendored.replace('.', "").parse() == Ok(system_version),
+            "the bundled database is compiled by the vendored libmagic {}, \
+             which the system libmagic {}.{} may not load; \
+             link a system libmagic of the same version or disable `bundled-database`",
+            vendored,
+            system_version / 100,
+            system_version % 100
+        );
     }
+    println!("cargo:rerun-if-env-changed=FILE_COMPILE");
 
-    Command::new("sh")
-        .current_dir("file/")
-        .args(configure_args)
-        .status()
-        .unwrap();
+    let mut compile = match std::env::var_os("FILE_COMPILE") {
+        Some(file) => {
+            let mut file = Command::new(file);
+            file.arg("-C").arg("-m");
+            file
+        }
+        None => Command::new(build_magic_compile(out_dir)),
+    };
+    compile.arg(&magdir);
+
+    // both write `Magdir.mgc` into the working directory
+    let status = compile.current_dir(out_dir).status().unwrap_or_else(|err| {
+        panic!(
+            "could not run {:?} to compile the bundled magic database: {}",
+            compile, err
+        )
+    });
+    assert!(
+        status.success(),
+        "{:?} failed with {} compiling the bundled magic database",
+        compile,
+        status
+    );
+    std::fs::rename(format!("{}/Magdir.mgc", out_dir), &bundled).unwrap();
+}
 
-    // make -j4
-    Command::new("/usr/bin/make")
-        .current_dir("file/")
-        .arg("-j4")
-        .status()
-        .unwrap();
+/// Driver compiling the magic source file or directory given as its argument into the working directory
+const MAGIC_COMPILE_C: &str = r#"/* generated by build.rs */
+#include <stdio.h>
+#include "magic.h"
+
+int
+main(int argc, char *argv[])
+{
+	magic_t ms;
+
+	if (argc != 2) {
+		fprintf(stderr, "usage: magic_compile <magic>\n");
+		return 2;
+	}
+	if ((ms = magic_open(MAGIC_NONE)) == NULL) {
+		perror("magic_open");
+		return 1;
+	}
+	if (magic_compile(ms, argv[1]) == -1) {
+		fprintf(stderr, "magic_compile: %s\n", magic_error(ms));
+		return 1;
+	}
+	magic_close(ms);
+	return 0;
+}
+"#;
+
+/// Builds `magic_compile` for the host from the `file` submodule, returning the path of the executable
+///
+/// The sources are compiled once more with a `config.h` for the host in `$OUT_DIR/host`,
+/// so the database is compiled with the vendored version even when cross-compiling or linking a system libmagic.
+/// The compiler is picked by the `cc` crate from `HOST_CC` or `CC_<host>`.
+fn build_magic_compile(out_dir: &str) -> std::path::PathBuf {
+    let host = std::env::var("HOST").unwrap();
+    let (os, env) = match host.split('-').collect::<Vec<_>>().as_slice() {
+        [.., "linux", env] if env.starts_with("musl") => ("linux", "musl"),
+        [.., "linux", env] if env.starts_with("gnu") => ("linux", "gnu"),
+        [.., "apple", "darwin"] => ("macos", ""),
+        [.., "windows", "gnu"] => ("windows", "gnu"),
+        _ => ("", ""),
+    };
+    let target = describe_target(os, env).unwrap_or_else(|| {
+        panic!(
+            "building `magic_compile` for a {} host is not supported; \
+             set FILE_COMPILE to a `file` binary of the vendored version to compile the bundled database",
+            host
+        )
+    });
+    let version = file_version();
+
+    let host_dir = format!("{}/host", out_dir);
+    let src_dir = copy_sources(&host_dir);
+    write_magic_h(&src_dir, &version);
+    write_config_h(&src_dir, &version, &target, &[]);
+    let driver = format!("{}/magic_compile.c", src_dir);
+    std::fs::write(&driver, MAGIC_COMPILE_C).unwrap();
+
+    let mut build = cc::Build::new();
+    build
+        .target(&host)
+        .host(&host)
+        .cargo_metadata(false)
+        // `RUSTFLAGS` are meant for the target
+        .inherit_rustflags(false)
+        .include(&src_dir)
+        .define("HAVE_CONFIG_H", None)
+        .define("MAGIC", "\"/usr/share/misc/magic\"")
+        .warnings(false);
+    if target.windows {
+        build.include("libgnurx");
+    }
+    let mut sources = vec![driver];
+    sources.extend(
+        LIBMAGIC_SOURCES
+            .iter()
+            .map(|source| format!("{}/{}", src_dir, source)),
+    );
+    sources.extend(
+        REPLACED_FUNCTIONS
+            .iter()
+            .filter(|function| !target.provided.contains(function))
+            .map(|function| format!("{}/{}.c", src_dir, function)),
+    );
+    if target.windows {
+        sources.push("libgnurx/regex.c".to_string());
+    }
 
-    // make install
-    Command::new("/usr/bin/make")
-        .current_dir("file/")
-        .arg("install")
+    // the build script runs on the host, so its executable suffix is the host's
+    let executable = format!("{}/magic_compile{}", host_dir, std::env::consts::EXE_SUFFIX);
+    let mut compile = build
+        .try_get_compiler()
+        .unwrap_or_else(|err| panic!("no C compiler for the host {}: {}", host, err))
+        .to_command();
+    compile.args(&sources).arg("-o").arg(&executable);
+    if target.windows {
+        compile.arg("-lshlwapi");
+    }
+    let status = compile
         .status()
-        .unwrap();
+        .unwrap_or_else(|err| panic!("could not run {:?}: {}", compile, err));
+    assert!(
+        status.success(),
+        "could not build `magic_compile` for the host {} with {:?}: {}; \
+         set HOST_CC for a C compiler targeting it, or FILE_COMPILE to a `file` binary of the vendored version",
+        host,
+        compile,
+        status
+    );
+    executable.into()
+}
 
-    println!("cargo:rustc-flags=-l static=magic");
-    println!("cargo:rustc-link-search=native={}/lib", &install_path);
-    let mut builder = bindgen::Builder::default()
-        .header(format!("{}/include/magic.h", &install_path))
-        .clang_arg(format!("-I{}/include/", &install_path));
-
-    if rustc_target.starts_with("x86_64-unknown-linux-musl") {
-        builder = builder.clang_arg(format!(
-            "-I/opt/x86_64-linux-musl/x86_64-linux-musl/include"
-        ));
-    } else if rustc_target.starts_with("aarch64-unknown-linux-musl") {
-        builder = builder.clang_arg(format!(
-            "-I/opt/aarch64-linux-musl/aarch64-linux-musl/include"
-        ));
-    } else if rustc_target.starts_with("x86_64-unknown-linux-gnu") {
-        //println!("cargo:rustc-flags=-l static=z");
-        //println!("cargo:rustc-link-search=native=/usr/lib/x86_64-linux-gnu");
-        builder = builder.clang_arg(format!("-I/usr/include/x86_64-linux-gnu"));
-    }
+/// libmagic sources in `file/src`, `libmagic_la_SOURCES` of its `Makefile.am`
+const LIBMAGIC_SOURCES: [&str; 20] = [
+    "buffer.c",
+    "magic.c",
+    "apprentice.c",
+    "softmagic.c",
+    "ascmagic.c",
+    "encoding.c",
+    "compress.c",
+    "is_csv.c",
+    "is_json.c",
+    "is_simh.c",
+    "is_tar.c",
+    "readelf.c",
+    "print.c",
+    "fsmagic.c",
+    "funcs.c",
+    "apptype.c",
+    "der.c",
+    "cdf.c",
+    "cdf_time.c",
+    "readcdf.c",
+];
+
+/// Functions with a replacement `file/src/<function>.c` for C libraries lacking them,
+/// `AC_REPLACE_FUNCS` of its `configure.ac`
+const REPLACED_FUNCTIONS: [&str; 13] = [
+    "asprintf",
+    "vasprintf",
+    "strlcpy",
+    "strlcat",
+    "getline",
+    "ctime_r",
+    "asctime_r",
+    "localtime_r",
+    "gmtime_r",
+    "pread",
+    "strcasestr",
+    "fmtcheck",
+    "dprintf",
+];
+
+/// `HAVE_*` entries of `config.h` for every supported target
+const HAVE_COMMON: [&str; 18] = [
+    "STDINT_H",
+    "INTTYPES_H",
+    "STDLIB_H",
+    "STRING_H",
+    "LIMITS_H",
+    "FCNTL_H",
+    "LOCALE_H",
+    "SYS_STAT_H",
+    "SYS_TYPES_H",
+    "SYS_TIME_H",
+    "UNISTD_H",
+    "WCHAR_H",
+    "WCTYPE_H",
+    "GETOPT_H",
+    "MBSTATE_T",
+    "STRERROR",
+    "STRTOUL",
+    "STRTOF",
+];
+
+/// `HAVE_*` entries of `config.h` for unix targets
+const HAVE_UNIX: [&str; 24] = [
+    "SYS_MMAN_H",
+    "SYS_WAIT_H",
+    "SYS_IOCTL_H",
+    "SIGNAL_H",
+    "SPAWN_H",
+    "UTIME_H",
+    "UTIME",
+    "UTIMES",
+    "MMAP",
+    "MKSTEMP",
+    "FORK",
+    "WORKING_FORK",
+    "VFORK",
+    "WORKING_VFORK",
+    "POSIX_SPAWNP",
+    "MBRTOWC",
+    "WCWIDTH",
+    "NEWLOCALE",
+    "USELOCALE",
+    "FREELOCALE",
+    "STRUCT_STAT_ST_RDEV",
+    "STRUCT_TM_TM_GMTOFF",
+    "STRUCT_TM_TM_ZONE",
+    "VISIBILITY",
+];
+
+/// What `configure` would find out about the target, as far as libmagic is concerned
+struct Target {
+    /// `HAVE_*` entries of `config.h` besides the replaced functions
+    have: Vec<&'static str>,
+    /// Functions of `REPLACED_FUNCTIONS` the C library provides
+    provided: Vec<&'static str>,
+    windows: bool,
+}
 
-    builder
-        .allowlist_var("MAGIC_.*")
-        .allowlist_function("magic_.*")
-        .layout_tests(false)
-        .generate()
-        .unwrap()
-        .write_to_file("src/magic/magic_sys.rs")
-        .unwrap();
+/// Describes the target being built for, failing for targets without a known `config.h`
+fn target() -> Target {
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let env = std::env::var("CARGO_CFG_TARGET_ENV").unwrap();
+    describe_target(&os, &env).unwrap_or_else(|| {
+        panic!(
+            "building the vendored libmagic for {} is not supported; \
+             enable the `system` feature to link a libmagic built for it instead",
+            std::env::var("TARGET").unwrap()
+        )
+    })
 }
 
-fn build_and_statically_link_windows(out_dir: &str) {
-    let install_path = format!("{}/build", out_dir);
-    std::fs::remove_dir_all(&install_path);
-    std::fs::create_dir(&install_path);
-    let mut configure_args: Vec<String> = Vec::from([
-        "./configure",
-        "--host=x86_64-w64-mingw32",
-        &format!("--prefix={}", &install_path),
-    ])
-    .into_iter()
-    .map(|i| i.to_string())
-    .collect();
-
-    Command::new("sh")
-        .current_dir("libgnurx/")
-        .args(&configure_args)
-        .status()
-        .unwrap();
+/// Describes the target with the given `target_os` and `target_env`, if its `config.h` is known
+fn describe_target(os: &str, env: &str) -> Option<Target> {
+    let target = match (os, env) {
+        // glibc only has `strlcpy` and `strlcat` since 2.38, the replacements are fine with newer ones
+        ("linux", "gnu") => unix_target(
+            &["SYS_SYSMACROS_H", "PIPE2", "MKOSTEMP"],
+            &["strlcpy", "strlcat", "fmtcheck"],
+        ),
+        ("linux", "musl") => unix_target(&["SYS_SYSMACROS_H", "PIPE2", "MKOSTEMP"], &["fmtcheck"]),
+        ("macos", _) => unix_target(&["XLOCALE_H"], &[]),
+        ("windows", "gnu") => Target {
+            have: HAVE_COMMON
+                .iter()
+                .chain(&["SYS_UTIME_H", "UTIME"])
+                .copied()
+                .collect(),
+            provided: Vec::new(),
+            windows: true,
+        },
+        _ => return None,
+    };
+    Some(target)
+}
 
-    // make -j2
-    Command::new("/usr/bin/make")
-        .current_dir("libgnurx/")
-        .arg("clean")
-        .status()
-        .unwrap();
+/// Describes a unix target having the `extra` entries and lacking the `lacking` functions
+fn unix_target(extra: &[&'static str], lacking: &[&str]) -> Target {
+    Target {
+        have: HAVE_COMMON
+            .iter()
+            .chain(HAVE_UNIX.iter())
+            .chain(extra)
+            .copied()
+            .collect(),
+        provided: REPLACED_FUNCTIONS
+            .iter()
+            .filter(|function| !lacking.contains(function))
+            .copied()
+            .collect(),
+        windows: false,
+    }
+}
 
-    // make -j2
-    Command::new("/usr/bin/make")
-        .current_dir("libgnurx/")
-        .arg("-j2")
-        .status()
-        .unwrap();
+/// Compiles the libmagic of the `file` submodule with the `cc` crate and links it statically
+///
+/// Instead of running `configure`, `config.h` is generated from what is known about the target.
+/// The compiler and its flags are picked by the `cc` crate from `TARGET`, `CC` and `CFLAGS`
+/// (or their `_<target>` variants), so the build needs nothing but a C compiler for the target.
+fn build_vendored(out_dir: &str) {
+    assert!(
+        Path::new("file/src/magic.h.in").exists(),
+        "the `file` submodule is not checked out; run `git submodule update --init`, \
+         or enable the `system` feature to link an installed libmagic"
+    );
+    println!("cargo:rerun-if-changed=file/src");
+    println!("cargo:rerun-if-changed=file/configure.ac");
+
+    let target = target();
+    let version = file_version();
+    let compression = compression();
+    announce_codecs(compression.features.iter().copied());
+    // the crate only links the codec `-sys` crates into the vendored build
+    println!("cargo:rustc-cfg=libmagic_vendored");
+
+    // the generated headers go next to the copied sources, which find them there first
+    let src_dir = copy_sources(out_dir);
+    write_magic_h(&src_dir, &version);
+    write_config_h(&src_dir, &version, &target, &compression.defines);
+
+    let mut build = cc::Build::new();
+    build
+        .include(&src_dir)
+        .includes(&compression.include_dirs)
+        .define("HAVE_CONFIG_H", None)
+        // `libmagic` falls back to this database without `MAGIC` in the environment
+        .define("MAGIC", "\"/usr/share/misc/magic\"")
+        .warnings(false);
+    if target.windows {
+        build.include("libgnurx");
+    } else {
+        build.flag("-fvisibility=hidden");
+    }
+    for source in LIBMAGIC_SOURCES {
+        build.file(format!("{}/{}", src_dir, source));
+    }
+    for function in REPLACED_FUNCTIONS {
+        if !target.provided.contains(&function) {
+            build.file(format!("{}/{}.c", src_dir, function));
+        }
+    }
+    compile(&mut build, "magic");
 
-    // make -j2
-    Command::new("/usr/bin/make")
-        .current_dir("libgnurx/")
-        .arg("install")
-        .status()
-        .unwrap();
+    if let Some(src_dir) = &compression.lzlib {
+        build_lzlib(src_dir);
+    }
+    if target.windows {
+        println!("cargo:rerun-if-changed=libgnurx");
+        let mut regex = cc::Build::new();
+        regex
+            .file("libgnurx/regex.c")
+            .include("libgnurx")
+            .warnings(false);
+        compile(&mut regex, "regex");
+        println!("cargo:rustc-link-lib=shlwapi");
+    }
 
-    // windows patch
-    apply_patches();
+    let header = format!("{}/magic.h", src_dir);
+    bindings(out_dir, Path::new(&header), &[format!("-I{}", src_dir)]);
+}
 
-    // autoreconf -f -i
-    Command::new("/usr/bin/autoreconf")
-        .current_dir("file/")
-        .arg("-f")
-        .arg("-i")
-        .status()
-        .unwrap();
+/// Copies the C sources and headers of `file/src` to `<dir>/file`, returning the copy
+///
+/// The submodule is only read, so concurrent builds for different targets cannot interfere.
+/// `magic.h` and `config.h` are left out: an in-tree autotools build may have generated them,
+/// and `#include "..."` would pick them up from the directory of the including source.
+fn copy_sources(dir: &str) -> String {
+    let src_dir = format!("{}/file", dir);
+    // start over, so that sources removed from the submodule do not linger
+    if let Err(err) = std::fs::remove_dir_all(&src_dir) {
+        assert!(
+            err.kind() == std::io::ErrorKind::NotFound,
+            "could not remove {}: {}",
+            src_dir,
+            err
+        );
+    }
+    std::fs::create_dir_all(&src_dir).unwrap();
+
+    let entries = std::fs::read_dir("file/src")
+        .unwrap_or_else(|err| panic!("could not read file/src: {}", err));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let source = name.ends_with(".c") || name.ends_with(".h");
+        if source && name != "magic.h" && name != "config.h" {
+            std::fs::copy(&path, format!("{}/{}", src_dir, name)).unwrap_or_else(|err| {
+                panic!("could not copy {} to {}: {}", path.display(), src_dir, err)
+            });
+        }
+    }
+    src_dir
+}
 
-    // make distclean
-    Command::new("/usr/bin/make")
-        .current_dir("file/")
-        .arg("distclean")
-        .status();
-    // ./configure --disable-silent-rules --enable-static=true --enable-shared=false --prefix=$(pwd)/build CC=x86_64-linux-musl-gcc
-    let mut configure_args: Vec<String> = Vec::from([
-        "./configure",
-        "--disable-silent-rules",
-        "--enable-static=yes",
-        "--enable-shared=no",
-        "--disable-zlib",
-        "--disable-bzlib",
-        "--disable-xzlib",
-        "--disable-zstdlib",
-        "--disable-lzlib",
-        "--host=x86_64-w64-mingw32",
-        &format!("--prefix={}", &install_path),
-    ])
-    .into_iter()
-    .map(|i| i.to_string())
-    .collect();
-
-    let mut win_env: HashMap<String, String> = std::env::vars().collect();
-
-    win_env.insert("CC".to_string(), "x86_64-w64-mingw32-gcc".to_string());
-    win_env.insert("CXX".to_string(), "x86_64-w64-mingw32-c++".to_string());
-    win_env.insert("CFLAGS".to_string(), format!("-I{}/include", install_path));
-    win_env.insert(
-        "LDFLAGS".to_string(),
-        //format!("-L{}/lib -lshlwapi", install_path),
-        format!("-L{}/lib -L/usr/x86_64-w64-mingw32/lib -lshlwapi", install_path),
+/// Compiles the sources of `build` into the static library `name` and links it
+fn compile(build: &mut cc::Build, name: &str) {
+    build.try_compile(name).unwrap_or_else(|err| {
+        panic!(
+            "could not compile {}: {}; set CC and CFLAGS (or CC_<target> and CFLAGS_<target>) \
+             for a C compiler targeting {}",
+            name,
+            err,
+            std::env::var("TARGET").unwrap()
+        )
+    });
+}
+
+/// Reads the version from `AC_INIT` of `file/configure.ac`, e.g. `5.45`
+fn file_version() -> String {
+    let configure = std::fs::read_to_string("file/configure.ac")
+        .unwrap_or_else(|err| panic!("could not read file/configure.ac: {}", err));
+    configure
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("AC_INIT("))
+        .and_then(|args| args.split(',').nth(1))
+        .map(|version| version.trim_matches(|c: char| c == '[' || c == ']' || c.is_whitespace()))
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| panic!("no version in AC_INIT of file/configure.ac"))
+        .to_string()
+}
+
+/// Writes `magic.h` from `magic.h.in`, substituting the version like `src/Makefile.am` does
+fn write_magic_h(include_dir: &str, version: &str) {
+    let template = std::fs::read_to_string("file/src/magic.h.in")
+        .unwrap_or_else(|err| panic!("could not read file/src/magic.h.in: {}", err));
+    let header = template.replace("X.YY", &version.replace('.', ""));
+    std::fs::write(format!("{}/magic.h", include_dir), header).unwrap();
+}
+
+/// Writes the `config.h` that `configure` would generate for `target`
+fn write_config_h(include_dir: &str, version: &str, target: &Target, defines: &[&str]) {
+    let mut config = String::from("/* generated by build.rs */\n");
+    // `AC_USE_SYSTEM_EXTENSIONS`
+    for extension in [
+        "_GNU_SOURCE",
+        "_ALL_SOURCE",
+        "_DARWIN_C_SOURCE",
+        "__EXTENSIONS__",
+    ] {
+        config += &format!("#ifndef {0}\n# define {0} 1\n#endif\n", extension);
+    }
+    config += &format!(
+        "#define PACKAGE \"file\"\n#define VERSION \"{}\"\n",
+        version
     );
+    // `--enable-elf` and `--enable-elf-core` are the defaults
+    config += "#define BUILTIN_ELF 1\n#define ELFCORE 1\n";
+    for have in &target.have {
+        config += &format!("#define HAVE_{} 1\n", have);
+    }
+    for function in &target.provided {
+        config += &format!("#define HAVE_{} 1\n", function.to_uppercase());
+    }
+    for define in defines {
+        config += &format!("#define {} 1\n", define);
+    }
+    std::fs::write(format!("{}/config.h", include_dir), config).unwrap();
+}
 
-    Command::new("sh")
-        .current_dir("file/")
-        .envs(&win_env)
-        .args(configure_args)
-        .status()
-        .unwrap();
+/// libmagic versions with pregenerated bindings in `src/magic/bindings/`
+const BINDINGS_VERSIONS: [u32; 2] = [544, 545];
+
+/// Provides the bindings for the libmagic declared in `header` as `$OUT_DIR/magic_sys.rs`
+///
+/// By default the pregenerated bindings of the newest version up to the one in `header` are used,
+/// falling back to the oldest ones for older versions. Function signatures have not changed since,
+/// the constants added later are defined in `src/magic/mod.rs` as well. The `bindgen` feature generates them from `header` instead,
+/// which needs libclang, and checks that they match the pregenerated ones of the same version.
+fn bindings(out_dir: &str, header: &Path, clang_args: &[String]) {
+    println!("cargo:rerun-if-changed=src/magic/bindings");
+    let version = header_version(header);
+    let out = format!("{}/magic_sys.rs", out_dir);
+
+    #[cfg(feature = "bindgen")]
+    {
+        let generated = bindgen::Builder::default()
+            .header(header.to_string_lossy())
+            .clang_args(clang_args)
+            .allowlist_var("MAGIC_.*")
+            .allowlist_function("magic_.*")
+            .layout_tests(false)
+            .generate()
+            .unwrap_or_else(|err| {
+                panic!(
+                    "could not generate bindings for {}: {}",
+                    header.display(),
+                    err
+                )
+            });
+        generated.write_to_file(&out).unwrap();
+
+        let pregenerated = format!("src/magic/bindings/magic_sys_{}.rs", version);
+        match std::fs::read_to_string(&pregenerated) {
+            Ok(pregenerated_bindings) => assert!(
+                without_whitespace(&pregenerated_bindings)
+                    == without_whitespace(&generated.to_string()),
+                "bindings generated from {} differ from {}, compare with {} and update them",
+                header.display(),
+                pregenerated,
+                out
+            ),
+            Err(_) => println!(
+                "cargo:warning=no pregenerated bindings for libmagic {}, add {} as {}",
+                version, out, pregenerated
+            ),
+        }
+    }
 
-    // make -j4
-    Command::new("/usr/bin/make")
-        .current_dir("file/")
-        .envs(&win_env)
-        .arg("-j4")
-        .status()
+    #[cfg(not(feature = "bindgen"))]
+    {
+        let _ = clang_args;
+        let selected = BINDINGS_VERSIONS
+            .iter()
+            .rev()
+            .find(|v| **v <= version)
+            .unwrap_or(&BINDINGS_VERSIONS[0]);
+        if *selected != version {
+            println!(
+                "cargo:warning=using bindings of libmagic {} for libmagic {}, enable the `bindgen` feature for exact ones",
+                selected, version
+            );
+        }
+        std::fs::copy(
+            format!("src/magic/bindings/magic_sys_{}.rs", selected),
+            &out,
+        )
         .unwrap();
+    }
+}
 
-    // make install
-    Command::new("/usr/bin/make")
-        .current_dir("file/")
-        .envs(&win_env)
-        .arg("install")
-        .status()
-        .unwrap();
+/// Reads `MAGIC_VERSION` from `magic.h`
+fn header_version(header: &Path) -> u32 {
+    let content = std::fs::read_to_string(header)
+        .unwrap_or_else(|err| panic!("could not read {}: {}", header.display(), err));
+    content
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix('#'))
+        .filter_map(|line| line.trim_start().strip_prefix("define"))
+        .find_map(|line| {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("MAGIC_VERSION") => tokens.next()?.parse().ok(),
+                _ => None,
+            }
+        })
+        .unwrap_or_else(|| panic!("no MAGIC_VERSION in {}", header.display()))
+}
 
-    println!("cargo:rustc-link-search=native=/usr/x86_64-w64-mingw32/lib");
-    println!("cargo:rustc-flags=-l static=shlwapi");
-    println!("cargo:rustc-flags=-l static=magic");
-    println!("cargo:rustc-flags=-l static=regex");
-    println!("cargo:rustc-link-search=native={}/lib", install_path);
-    bindgen::Builder::default()
-        .header(format!("{}/include/magic.h", &install_path))
-        .clang_arg(format!("-I{}/include", &install_path))
-        .clang_arg(format!("-L/usr/x86_64-w64-mingw32/lib"))
-        .clang_arg(format!("-L{}/lib", &install_path))
-        .allowlist_var("MAGIC_.*")
-        .allowlist_function("magic_.*")
-        .layout_tests(false)
-        .generate()
-        .unwrap()
-        .write_to_file("src/magic/magic_sys.rs")
-        .unwrap();
+#[cfg(feature = "bindgen")]
+fn without_whitespace(bindings: &str) -> String {
+    bindings
+        .lines()
+        // the first line names the bindgen version
+        .filter(|line| !line.starts_with("/* automatically generated"))
+        .flat_map(str::chars)
+        .filter(|c| !c.is_whitespace())
+        .collect()
+}
+
+/// Compression libraries libmagic can decompress with instead of running an external program: cargo feature,
+/// the `links` name of the `-sys` crate building it if there is one, the library libmagic links
+/// and what `configure` defines when it is enabled
+const CODECS: [(&str, Option<&str>, &str, &[&str]); 5] = [
+    ("ZLIB", Some("Z"), "z", &["HAVE_ZLIB_H", "ZLIBSUPPORT"]),
+    (
+        "BZIP2",
+        Some("BZIP2"),
+        "bz2",
+        &["HAVE_BZLIB_H", "BZLIBSUPPORT"],
+    ),
+    ("XZ", Some("LZMA"), "lzma", &["HAVE_LZMA_H", "XZLIBSUPPORT"]),
+    (
+        "ZSTD",
+        Some("ZSTD"),
+        "zstd",
+        &["HAVE_ZSTD_H", "HAVE_ZSTD_ERRORS_H", "ZSTDLIBSUPPORT"],
+    ),
+    ("LZIP", None, "lz", &["HAVE_LZLIB_H", "LZLIBSUPPORT"]),
+];
+
+/// `config.h` entries and include directories for the compression libraries enabled by cargo features
+struct Compression {
+    /// Cargo features of the enabled codecs
+    features: Vec<&'static str>,
+    defines: Vec<&'static str>,
+    include_dirs: Vec<String>,
+    /// Sources of lzlib to compile for the `lzip` feature
+    lzlib: Option<String>,
 }
 
-fn build_and_statically_link_macos() {
-    !unimplemented!("macos")
+/// Collects what libmagic needs to use each compression library whose cargo feature is set
+///
+/// zlib, bzip2, xz and zstd are built as static libraries for the target by their `-sys` crates,
+/// which also link them into the final binary. lzlib has no such crate, it is built from `LZLIB_SRC_DIR`.
+fn compression() -> Compression {
+    let mut compression = Compression {
+        features: Vec::new(),
+        defines: Vec::new(),
+        include_dirs: Vec::new(),
+        lzlib: None,
+    };
+
+    for (feature, links, _, defines) in CODECS {
+        if std::env::var_os(format!("CARGO_FEATURE_{}", feature)).is_none() {
+            continue;
+        }
+        compression.defines.extend(defines);
+        compression.features.push(feature);
+
+        match links {
+            Some(links) => {
+                let include =
+                    std::env::var(format!("DEP_{}_INCLUDE", links))
+                        .ok()
+                        .or(std::env::var(format!("DEP_{}_ROOT", links))
+                            .ok()
+                            .map(|root| format!("{}/include", root)));
+                compression.include_dirs.extend(include);
+            }
+            None => {
+                println!("cargo:rerun-if-env-changed=LZLIB_SRC_DIR");
+                let src_dir = std::env::var("LZLIB_SRC_DIR").unwrap_or_else(|_| {
+                    panic!(
+                        "the `lzip` feature builds lzlib from source; \
+                         set LZLIB_SRC_DIR to an unpacked release from https://www.nongnu.org/lzip/lzlib.html"
+                    )
+                });
+                compression.include_dirs.push(src_dir.clone());
+                compression.lzlib = Some(src_dir);
+            }
+        }
+    }
+
+    compression
+}
+
+/// Compiles lzlib from `src_dir` for the `lzip` feature
+fn build_lzlib(src_dir: &str) {
+    let lzlib = Path::new(src_dir).join("lzlib.c");
+    assert!(
+        lzlib.exists(),
+        "LZLIB_SRC_DIR={} contains no lzlib.c, point it to an unpacked lzlib release",
+        src_dir
+    );
+    println!("cargo:rerun-if-changed={}", src_dir);
+
+    // `lzlib.c` includes the other sources
+    let mut build = cc::Build::new();
+    build.file(lzlib).include(src_dir).warnings(false);
+    compile(&mut build, "lz");
 }
@@ -0,0 +1,219 @@
+//! Identification from async code on tokio's blocking thread pool
+//!
+//! `libmagic` performs blocking file I/O and CPU bound matching,
+//! so [`AsyncMagic`] runs every call via [`tokio::task::spawn_blocking`] on a cookie taken from a [`CookiePool`].
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::Semaphore;
+
+use crate::cookie::{self, Identified, Limits};
+use crate::pool::{CookiePool, PoolError};
+
+/// Cloneable handle identifying content from async code
+///
+/// At most [`CookiePool::max_size()`] identifications run at the same time, further calls wait asynchronously.
+///
+/// Dropping a returned future cancels the call while it is still waiting for a cookie.
+/// Once `libmagic` is running it cannot be interrupted: the blocking task finishes in the background
+/// and keeps its slot until then, its result is discarded.
+#[derive(Clone)]
+pub struct AsyncMagic {
+    pool: Arc<CookiePool>,
+    permits: Arc<Semaphore>,
+    bytes_max: usize,
+}
+
+impl AsyncMagic {
+    /// Creates a handle running identifications on cookies from `pool`
+    pub fn new(pool: CookiePool) -> Result<AsyncMagic, PoolError> {
+        let bytes_max = pool
+            .get()?
+            .limits()
+            .map_or(Limits::default().bytes_max(), |limits| limits.bytes_max());
+
+        Ok(AsyncMagic {
+            permits: Arc::new(Semaphore::new(pool.max_size())),
+            pool: Arc::new(pool),
+            bytes_max,
+        })
+    }
+
+    /// Identifies the file at `path`, see [`Cookie::file()`](crate::Cookie::file)
+    pub async fn identify_path<P: AsRef<Path>>(&self, path: P) -> Result<String, AsyncError> {
+        let path: PathBuf = path.as_ref().into();
+        self.run(move |cookie| cookie.file(path)).await
+    }
+
+    /// Identifies `bytes`, see [`Cookie::buffer()`](crate::Cookie::buffer)
+    pub async fn identify_bytes<B>(&self, bytes: B) -> Result<String, AsyncError>
+    where
+        B: AsRef<[u8]> + Send + 'static,
+    {
+        self.run(move |cookie| cookie.buffer(bytes.as_ref())).await
+    }
+
+    /// Identifies the content of `reader` without reading all of it, see [`Cookie::reader()`](crate::Cookie::reader)
+    ///
+    /// The consumed prefix is handed back along with the result,
+    /// use [`Identified::into_async_reader()`] to pass the stream on unchanged.
+    pub async fn identify_async_read<R>(&self, mut reader: R) -> Result<Identified<R>, AsyncError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut prefix = Vec::new();
+        (&mut reader)
+            .take(self.bytes_max as u64)
+            .read_to_end(&mut prefix)
+            .await?;
+
+        let (result, prefix) = self
+            .run(move |cookie| cookie.buffer(&prefix).map(|result| (result, prefix)))
+            .await?;
+        Ok(Identified::new(result, prefix, reader))
+    }
+
+    async fn run<T, F>(&self, identify: F) -> Result<T, AsyncError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&cookie::Cookie<cookie::Load>) -> Result<T, cookie::Error> + Send + 'static,
+    {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let pool = self.pool.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let cookie = pool.get()?;
+            Ok(identify(&cookie)?)
+        })
+        .await?
+    }
+}
+
+impl<R: AsyncRead + Unpin> Identified<R> {
+    /// Returns an async reader yielding the consumed prefix followed by the rest of the original reader
+    pub fn into_async_reader(self) -> tokio::io::Chain<std::io::Cursor<Vec<u8>>, R> {
+        let (_, prefix, reader) = self.into_parts();
+        std::io::Cursor::new(prefix).chain(reader)
+    }
+}
+
+/// Error within [`AsyncMagic`] functions
+#[derive(thiserror::Error, Debug)]
+pub enum AsyncError {
+    /// No cookie could be created
+    #[error(transparent)]
+    Pool(#[from] PoolError),
+    /// Identification failed
+    #[error(transparent)]
+    Magic(#[from] cookie::Error),
+    /// Reading the prefix failed
+    #[error("could not read content to identify")]
+    Io(#[from] std::io::Error),
+    /// The blocking task panicked
+    #[error("identification task failed")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::cookie::Flags;
+
+    fn magic(max_size: usize) -> AsyncMagic {
+        let compiled = cookie::tests::compiled("0\tstring\tPOOLTEST\tpool test data\n");
+        AsyncMagic::new(CookiePool::with_buffers(Flags::empty(), [compiled], max_size).unwrap())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn blocking_jobs_are_bounded_by_the_pool_size() {
+        let magic = magic(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let mut jobs = tokio::task::JoinSet::new();
+        for _ in 0..6 {
+            let (magic, running, peak) = (magic.clone(), running.clone(), peak.clone());
+            jobs.spawn(async move {
+                magic
+                    .run(move |cookie| {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(50));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        cookie.buffer(b"POOLTEST")
+                    })
+                    .await
+            });
+        }
+        while let Some(result) = jobs.join_next().await {
+            assert_eq!(result.unwrap().unwrap(), "pool test data");
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(magic.pool.size(), 2);
+    }
+
+    #[tokio::test]
+    async fn dropped_futures_release_permit_and_cookie() {
+        let magic = magic(1);
+        let (started, wait_started) = tokio::sync::oneshot::channel();
+        let (release, wait_release) = std::sync::mpsc::channel::<()>();
+
+        let mut running = Box::pin(magic.run(move |cookie| {
+            started.send(()).unwrap();
+            wait_release.recv().unwrap();
+            cookie.buffer(b"POOLTEST")
+        }));
+        tokio::select! {
+            _ = &mut running => unreachable!("the job waits to be released"),
+            _ = wait_started => {}
+        }
+
+        // waiting for the only permit, cancelled before getting it
+        let mut waiting = Box::pin(magic.identify_bytes(b"POOLTEST"));
+        tokio::select! {
+            biased;
+            _ = &mut waiting => unreachable!("the only permit is taken"),
+            _ = tokio::task::yield_now() => {}
+        }
+        drop(waiting);
+
+        // the blocking job keeps its slot until it finishes
+        drop(running);
+        assert_eq!(magic.permits.available_permits(), 0);
+        release.send(()).unwrap();
+
+        assert_eq!(
+            magic.identify_bytes(b"POOLTEST").await.unwrap(),
+            "pool test data"
+        );
+        assert_eq!(magic.permits.available_permits(), 1);
+        assert_eq!(magic.pool.size(), 1);
+        assert_eq!(magic.pool.idle(), 1);
+    }
+
+    #[tokio::test]
+    async fn async_read_only_consumes_bytes_max() {
+        let magic = magic(1);
+        let mut content = b"POOLTEST".to_vec();
+        content.resize(magic.bytes_max + 100, 0);
+
+        let identified = magic.identify_async_read(&content[..]).await.unwrap();
+        assert_eq!(identified.result(), "pool test data");
+        assert_eq!(identified.prefix().len(), magic.bytes_max);
+
+        let (_, _, rest) = identified.into_parts();
+        assert_eq!(rest.len(), 100);
+    }
+}
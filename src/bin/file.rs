@@ -1,20 +1,65 @@
 use libmagic_rs::{cookie, libmagic_version, Cookie};
 use log::*;
 
+/// Describes why a database could not be loaded
+fn load_error<S: cookie::State>(what: &str, err: &cookie::LoadError<S>) -> String {
+    format!(
+        "could not load {}: {}",
+        what,
+        err.explanation().to_string_lossy()
+    )
+}
+
+#[cfg(feature = "bundled-database")]
+fn load_bundled(cookie: Cookie<cookie::Open>) -> Result<Cookie<cookie::Load>, String> {
+    cookie
+        .load_bundled()
+        .map_err(|err| load_error("the bundled magic database", &err))
+}
+
+#[cfg(not(feature = "bundled-database"))]
+fn load_bundled(_cookie: Cookie<cookie::Open>) -> Result<Cookie<cookie::Load>, String> {
+    Err("no bundled magic database, built without the `bundled-database` feature".to_string())
+}
+
 pub fn main() {
     println!("libmagic version: {}", libmagic_version());
     let file_flags = cookie::Flags::ERROR;
 
     let cookie = Cookie::open(file_flags).unwrap();
-    let database = [
-        "magic.mgc",
-        //"/etc/magic.mgc",
-        //"/etc/magic",
-        //"/usr/share/misc/magic.mgc",
-    ]
-    .try_into()
-    .unwrap();
-    let cookie = cookie.load(&database).unwrap();
+    let candidates = cookie::DatabasePaths::discover(cookie::DatabaseAction::Load);
+    for candidate in &candidates {
+        debug!(
+            "magic database {} (exists: {})",
+            candidate.path().display(),
+            candidate.exists()
+        );
+    }
+    // fall back to the bundled database if no system database is found or it fails to load
+    let mut errors = Vec::new();
+    let loaded = if candidates.iter().any(|c| c.exists()) {
+        let database = candidates.as_slice().try_into().unwrap();
+        match cookie.load(&database) {
+            Ok(cookie) => Ok(cookie),
+            Err(err) => {
+                errors.push(load_error("the magic database", &err));
+                load_bundled(err.cookie())
+            }
+        }
+    } else {
+        errors.push("no magic database found, set MAGIC to the path of a magic.mgc".to_string());
+        load_bundled(cookie)
+    };
+    let cookie = match loaded {
+        Ok(cookie) => cookie,
+        Err(err) => {
+            errors.push(err);
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            std::process::exit(1);
+        }
+    };
 
     let args: Vec<String> = std::env::args().collect();
     let args_count = args.len();
@@ -57,11 +102,11 @@ pub fn main() {
             if entry.path().is_dir() {
                 continue;
             }
-            let tfpath = entry.path().to_string_lossy().to_string();
+            let tfpath = entry.path();
 
-            match cookie.file(&tfpath) {
+            match cookie.file(tfpath) {
                 Ok(result) => {
-                    println!("{}: {}", &tfpath, &result);
+                    println!("{}: {}", tfpath.display(), &result);
                 }
                 Err(e) => {
                     error!("{}: error :{}", fpath, e);
@@ -1,16 +1,41 @@
 #![deny(unsafe_code)]
 
+#[cfg(feature = "tokio")]
+pub mod async_magic;
 pub mod magic;
+pub mod pool;
+pub mod reload;
+
+// the vendored `libmagic` is built against these, linking them is up to us, see `compression()` in `build.rs`.
+// A system `libmagic` links its own codecs, so they are left out then.
+#[cfg(all(feature = "bzip2", libmagic_vendored))]
+extern crate bzip2_sys as _;
+#[cfg(all(feature = "zlib", libmagic_vendored))]
+extern crate libz_sys as _;
+#[cfg(all(feature = "xz", libmagic_vendored))]
+extern crate lzma_sys as _;
+#[cfg(all(feature = "zstd", libmagic_vendored))]
+extern crate zstd_sys as _;
 
 pub fn libmagic_version() -> libc::c_int {
     crate::magic::version()
 }
 
+/// Makes `magic_sys` API violations panic instead of returning an error
+///
+/// By default a violation, e.g. `libmagic` failing without setting an error message,
+/// is logged and returned as an error of kind [`ErrorKind::ApiViolation`](cookie::ErrorKind::ApiViolation).
+/// Strict mode is meant for tests that should not let such a violation pass unnoticed.
+pub fn set_strict_api(strict: bool) {
+    crate::magic::set_strict_api(strict)
+}
+
 /// Functionality for [`Cookie`]
 pub mod cookie {
     use std::convert::TryFrom;
     use std::ffi::CString;
-    use std::path::Path;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
 
     use crate::magic;
 
@@ -33,6 +58,8 @@ pub mod cookie {
                                     | Self::MIME_ENCODING.bits();
             const APPLE             = magic::MAGIC_APPLE;
             const EXTENSION         = magic::MAGIC_EXTENSION;
+            const COMPRESS_TRANSP   = magic::MAGIC_COMPRESS_TRANSP;
+            const NO_COMPRESS_FORK  = magic::MAGIC_NO_COMPRESS_FORK;
             const NODESC            = Self::EXTENSION.bits()
                                     | Self::MIME.bits()
                                     | Self::APPLE.bits();
@@ -47,6 +74,7 @@ pub mod cookie {
             const NO_CHECK_TOKENS   = magic::MAGIC_NO_CHECK_TOKENS;
             const NO_CHECK_ENCODING = magic::MAGIC_NO_CHECK_ENCODING;
             const NO_CHECK_JSON     = magic::MAGIC_NO_CHECK_JSON;
+            const NO_CHECK_SIMH     = magic::MAGIC_NO_CHECK_SIMH;
             const NO_CHECK_BUILTIN  = Self::NO_CHECK_COMPRESS.bits()
                                     | Self::NO_CHECK_TAR.bits()
                                     | Self::NO_CHECK_APPTYPE.bits()
@@ -66,10 +94,106 @@ pub mod cookie {
         }
     }
 
+    impl Flags {
+        /// Returns the flags honored by the linked `libmagic`
+        ///
+        /// `magic_setflags()` silently accepts bits it does not know about,
+        /// so support is derived from the [`libmagic_version()`](crate::libmagic_version) that introduced each flag,
+        /// as recorded in the `ChangeLog` of `file`.
+        /// `PRESERVE_ATIME` depends on the platform instead and is probed on a temporary cookie.
+        pub fn supported() -> Flags {
+            let version = crate::magic::version();
+            let mut supported = Flags::empty();
+            for (_, flag) in Flags::all().iter_names() {
+                supported.insert(flag);
+            }
+
+            for (flag, since) in [
+                // 2015-04-09 "Add --extension to list the known extensions for this file type"
+                (Flags::EXTENSION, 523),
+                // 2015-06-03 "PR/455: Implement -Z, look inside, but don't report on compression"
+                (Flags::COMPRESS_TRANSP, 523),
+                // 2018-07-25 "PR/23: Recognize JSON files", after 5.34 was released that day
+                (Flags::NO_CHECK_JSON, 535),
+                // 2019-12-15 "Add a flag to disable CSV file detection", part of 5.38
+                (Flags::NO_CHECK_CSV, 538),
+                // 2022-09-15 "Add MAGIC_NO_COMPRESS_FORK", after 5.43 was released on 2022-09-13
+                (Flags::NO_COMPRESS_FORK, 544),
+                // SIMH tape detection, `MAGIC_NO_CHECK_SIMH` is first defined in the `magic.h` of 5.45
+                (Flags::NO_CHECK_SIMH, 545),
+            ] {
+                if version < since {
+                    supported.remove(flag);
+                }
+            }
+
+            if let Ok(mut cookie) = crate::magic::open(0) {
+                if crate::magic::setflags(&cookie, Flags::PRESERVE_ATIME.bits() as _).is_err() {
+                    supported.remove(Flags::PRESERVE_ATIME);
+                }
+                crate::magic::close(&mut cookie);
+            }
+
+            supported
+        }
+    }
+
+    bitflags::bitflags! {
+        /// Compression formats `libmagic` can look into with [`Flags::COMPRESS`] without running an external decompressor
+        ///
+        /// The vendored `libmagic` gains each of them through the cargo feature of the same name,
+        /// see [`Compression::supported()`](Compression::supported).
+        #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+        pub struct Compression: u32 {
+            /// gzip, via zlib
+            const GZIP  = 1 << 0;
+            const BZIP2 = 1 << 1;
+            const XZ    = 1 << 2;
+            const ZSTD  = 1 << 3;
+            /// lzip, via lzlib
+            const LZIP  = 1 << 4;
+        }
+    }
+
+    impl std::fmt::Display for Compression {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            bitflags::parser::to_writer(self, f)
+        }
+    }
+
+    impl Compression {
+        /// Returns the compression formats the linked `libmagic` decompresses without running an external program
+        ///
+        /// Derived at build time from the codec features of the vendored `libmagic`,
+        /// or from the compression libraries a system `libmagic` links according to pkg-config or `LIBMAGIC_LIBS`,
+        /// and limited to what the `libmagic` version has built-in decompressors for.
+        ///
+        /// Since 5.44 `libmagic` decompresses in a forked child even with a built-in decompressor,
+        /// so [`Flags::NO_COMPRESS_FORK`] disables all of these as well.
+        pub fn supported() -> Compression {
+            let version = crate::libmagic_version();
+            env!("LIBMAGIC_CODECS")
+                .split_whitespace()
+                .filter_map(|codec| match codec {
+                    "zlib" => Some(Compression::GZIP),
+                    // 2019-12-15 "Add builtin decompressors for xz and and bzip", part of 5.38
+                    "bzip2" if version >= 538 => Some(Compression::BZIP2),
+                    "xz" if version >= 538 => Some(Compression::XZ),
+                    // 2022-09-14 "Add built-in zstd decompression support", after 5.43
+                    "zstd" if version >= 544 => Some(Compression::ZSTD),
+                    // 2022-09-15 "Add built-in lzip decompression support", after 5.43
+                    "lzip" if version >= 544 => Some(Compression::LZIP),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
     #[derive(thiserror::Error, Debug)]
     #[error("invalid database files path")]
     pub struct InvalidDatabasePathError {}
 
+    #[derive(Debug, Clone)]
     pub struct DatabasePaths {
         filenames: Option<CString>,
     }
@@ -87,9 +211,9 @@ pub mod cookie {
         {
             let filename = paths
                 .into_iter()
-                .map(|f| f.as_ref().to_string_lossy().into_owned())
-                .collect::<Vec<String>>()
-                .join(DATABASE_FILENAME_SEPARATOR);
+                .map(|f| path_to_bytes(f.as_ref()).into_owned())
+                .collect::<Vec<Vec<u8>>>()
+                .join(DATABASE_FILENAME_SEPARATOR.as_bytes());
 
             Ok(Self {
                 filenames: match filename.is_empty() {
@@ -98,6 +222,243 @@ pub mod cookie {
                 },
             })
         }
+
+        /// Resolves the database files `libmagic` would use by default for `action`
+        ///
+        /// This honors the `MAGIC` environment variable, `~/.magic.mgc` and the default compiled into `libmagic`,
+        /// in the same order as [`DatabasePaths::default()`](DatabasePaths::default) does when passed to a [`Cookie`].
+        #[doc(alias = "magic_getpath")]
+        pub fn discover(action: DatabaseAction) -> Vec<DatabaseCandidate> {
+            Self::default()
+                .resolve(action)
+                .into_iter()
+                .map(DatabaseCandidate::new)
+                .collect()
+        }
+
+        /// Splits these paths, or the default ones `libmagic` uses for `action`, into single paths
+        pub(crate) fn resolve(&self, action: DatabaseAction) -> Vec<PathBuf> {
+            let filenames = match crate::magic::getpath(self.filenames.as_deref(), action as _) {
+                Some(filenames) => filenames,
+                None => return Vec::new(),
+            };
+
+            filenames
+                .as_bytes()
+                .split(|b| DATABASE_FILENAME_SEPARATOR.as_bytes().contains(b))
+                .filter(|f| !f.is_empty())
+                .map(bytes_to_path)
+                .collect()
+        }
+
+        /// Resolves the single source file or directory to compile into one database, as an absolute path
+        fn compile_source(&self) -> std::io::Result<CString> {
+            let source = match self.resolve(DatabaseAction::Compile).as_slice() {
+                [source] => source.clone(),
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "expected exactly one magic source file or directory",
+                    ))
+                }
+            };
+            let source = std::env::current_dir()?.join(source);
+
+            path_to_cstring(&source)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+        }
+    }
+
+    /// Purpose a database is resolved for by [`DatabasePaths::discover()`](DatabasePaths::discover)
+    #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+    pub enum DatabaseAction {
+        /// [`Cookie::load()`](Cookie::load)
+        #[doc(alias = "FILE_LOAD")]
+        Load = magic::FILE_LOAD as _,
+        /// [`Cookie::check()`](Cookie::check)
+        #[doc(alias = "FILE_CHECK")]
+        Check = magic::FILE_CHECK as _,
+        /// [`Cookie::compile()`](Cookie::compile)
+        #[doc(alias = "FILE_COMPILE")]
+        Compile = magic::FILE_COMPILE as _,
+        /// [`Cookie::list()`](Cookie::list)
+        #[doc(alias = "FILE_LIST")]
+        List = magic::FILE_LIST as _,
+    }
+
+    /// Database file resolved by [`DatabasePaths::discover()`](DatabasePaths::discover)
+    #[derive(PartialEq, Eq, Hash, Debug, Clone)]
+    pub struct DatabaseCandidate {
+        path: PathBuf,
+        exists: bool,
+    }
+
+    impl DatabaseCandidate {
+        fn new(path: PathBuf) -> Self {
+            // `libmagic` also tries the compiled `<path>.mgc` next to a source file or directory
+            let mut compiled = path.clone().into_os_string();
+            compiled.push(".mgc");
+            let exists = path.exists() || Path::new(&compiled).exists();
+
+            Self { path, exists }
+        }
+
+        /// Path as resolved by `libmagic`
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Whether the path or its compiled `.mgc` variant exists
+        pub fn exists(&self) -> bool {
+            self.exists
+        }
+    }
+
+    impl TryFrom<&'_ [DatabaseCandidate]> for DatabasePaths {
+        type Error = InvalidDatabasePathError;
+
+        /// Invokes [`DatabasePaths::new()`](DatabasePaths::new) with the existing candidates only
+        fn try_from(
+            value: &[DatabaseCandidate],
+        ) -> Result<Self, <Self as TryFrom<&[DatabaseCandidate]>>::Error> {
+            Self::new(
+                value
+                    .iter()
+                    .filter(|c| c.exists())
+                    .map(DatabaseCandidate::path),
+            )
+        }
+    }
+
+    /// Compiled database held in memory, see [`Cookie::load_buffers()`](Cookie::load_buffers)
+    ///
+    /// Cloning is cheap and shares the underlying bytes.
+    #[derive(Debug, Clone)]
+    pub struct DatabaseBuffer(DatabaseBufferInner);
+
+    #[derive(Debug, Clone)]
+    enum DatabaseBufferInner {
+        Static(&'static [u8]),
+        Shared(std::sync::Arc<[u8]>),
+    }
+
+    impl DatabaseBuffer {
+        /// Returns the compiled database bytes
+        pub fn as_bytes(&self) -> &[u8] {
+            match &self.0 {
+                DatabaseBufferInner::Static(bytes) => bytes,
+                DatabaseBufferInner::Shared(bytes) => bytes,
+            }
+        }
+    }
+
+    #[cfg(feature = "bundled-database")]
+    impl DatabaseBuffer {
+        /// Returns the database compiled from `file/magic/Magdir` at build time
+        pub fn bundled() -> Self {
+            #[repr(C, align(8))]
+            struct Aligned<T: ?Sized>(T);
+
+            // `libmagic` reads the compiled database through `uint32_t` pointers
+            static BUNDLED: &Aligned<[u8]> =
+                &Aligned(*include_bytes!(concat!(env!("OUT_DIR"), "/magic.mgc")));
+
+            Self::from(&BUNDLED.0)
+        }
+    }
+
+    impl From<&'static [u8]> for DatabaseBuffer {
+        /// Borrows `value` if it is 8-byte aligned, otherwise copies it
+        ///
+        /// `libmagic` reads the database through `uint32_t` and `struct magic` pointers,
+        /// so a misaligned `include_bytes!()` must not be passed as is.
+        fn from(value: &'static [u8]) -> Self {
+            match value.as_ptr().align_offset(8) {
+                0 => Self(DatabaseBufferInner::Static(value)),
+                _ => Self(DatabaseBufferInner::Shared(value.into())),
+            }
+        }
+    }
+
+    impl From<std::sync::Arc<[u8]>> for DatabaseBuffer {
+        fn from(value: std::sync::Arc<[u8]>) -> Self {
+            Self(DatabaseBufferInner::Shared(value))
+        }
+    }
+
+    impl From<Vec<u8>> for DatabaseBuffer {
+        fn from(value: Vec<u8>) -> Self {
+            Self(DatabaseBufferInner::Shared(value.into()))
+        }
+    }
+
+    impl From<Box<[u8]>> for DatabaseBuffer {
+        fn from(value: Box<[u8]>) -> Self {
+            Self(DatabaseBufferInner::Shared(value.into()))
+        }
+    }
+
+    /// Converts `path` to what `libmagic` expects, byte for byte where the platform allows it
+    #[cfg(unix)]
+    fn path_to_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+        use std::os::unix::ffi::OsStrExt;
+
+        path.as_os_str().as_bytes().into()
+    }
+
+    #[cfg(not(unix))]
+    fn path_to_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+        match path.to_string_lossy() {
+            std::borrow::Cow::Borrowed(path) => path.as_bytes().into(),
+            std::borrow::Cow::Owned(path) => path.into_bytes().into(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+        use std::os::unix::ffi::OsStrExt;
+
+        std::ffi::OsStr::from_bytes(bytes).into()
+    }
+
+    #[cfg(not(unix))]
+    fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+        String::from_utf8_lossy(bytes).into_owned().into()
+    }
+
+    fn path_to_cstring(path: &Path) -> Result<CString, std::ffi::NulError> {
+        CString::new(path_to_bytes(path).into_owned())
+    }
+
+    /// Directory of its own for a single compilation, removed with everything in it on drop
+    struct StagingDir(PathBuf);
+
+    impl StagingDir {
+        fn new(parent: &Path) -> std::io::Result<Self> {
+            static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+            // the compilation may not run in the current working directory
+            let parent = std::env::current_dir()?.join(parent);
+            loop {
+                let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let path = parent.join(format!(".libmagic-compile-{}-{}", std::process::id(), n));
+                match std::fs::create_dir(&path) {
+                    Ok(_) => return Ok(Self(path)),
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for StagingDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
     }
 
     impl Default for DatabasePaths {
@@ -158,195 +519,1505 @@ pub mod cookie {
     databasepaths_try_from_impl!(std::path::PathBuf);
     databasepaths_try_from_impl!(String);
 
-    /// Error within several [`Cookie`] functions
+    /// Resource limits applied by `libmagic` while identifying content
     ///
-    /// Most functions on a [`Cookie`] can return an error from `libmagic`,
-    /// which unfortunately is not very structured.
-    #[derive(thiserror::Error, Debug)]
-    #[error("magic cookie error in `libmagic` function {} {:?}", .function, .source.explanation)]
-    pub struct Error {
-        function: &'static str,
-        //#[backtrace]
-        source: crate::magic::CookieError,
+    /// Each limit corresponds to one of the `MAGIC_PARAM_*` parameters of
+    /// `magic_setparam()` / `magic_getparam()`.
+    /// The [`Default`] values are the ones compiled into `libmagic` 5.45,
+    /// use [`Cookie::limits()`](Cookie::limits) to read the values actually in effect.
+    ///
+    /// `libmagic` stores most limits in 16 bits, [`Cookie::set_limits()`](Cookie::set_limits) rejects larger values.
+    /// Limits the linked `libmagic` does not know about yet (e.g. `MAGIC_PARAM_ELF_SHSIZE_MAX` before 5.45)
+    /// are not applied, and [`Cookie::limits()`](Cookie::limits) reports them as `None`.
+    #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+    pub struct Limits {
+        indir_max: usize,
+        name_max: usize,
+        elf_phnum_max: usize,
+        elf_shnum_max: usize,
+        elf_notes_max: usize,
+        regex_max: usize,
+        bytes_max: usize,
+        encoding_max: usize,
+        elf_shsize_max: Option<usize>,
     }
 
-    #[doc(hidden)]
-    #[derive(Debug)]
-    pub enum Open {}
+    impl Default for Limits {
+        fn default() -> Self {
+            Self {
+                indir_max: 50,
+                name_max: 50,
+                elf_phnum_max: 2048,
+                elf_shnum_max: 32768,
+                elf_notes_max: 256,
+                regex_max: 8192,
+                bytes_max: 7 * 1024 * 1024,
+                encoding_max: 64 * 1024,
+                elf_shsize_max: Some(128 * 1024 * 1024),
+            }
+        }
+    }
 
-    #[doc(hidden)]
-    #[derive(Debug)]
-    pub enum Load {}
+    impl Limits {
+        /// Conservative limits for identifying untrusted input
+        ///
+        /// Caps recursion through `indirect` and `use` rules, the size of regex matches,
+        /// the amount of ELF metadata parsed and the number of bytes read from each input,
+        /// trading some accuracy on unusual files for bounded time and memory.
+        pub fn hardened() -> Self {
+            Self {
+                indir_max: 15,
+                name_max: 30,
+                elf_phnum_max: 128,
+                elf_shnum_max: 1024,
+                elf_notes_max: 64,
+                regex_max: 4096,
+                bytes_max: 1024 * 1024,
+                encoding_max: 16 * 1024,
+                elf_shsize_max: Some(16 * 1024 * 1024),
+            }
+        }
 
-    mod private {
-        pub trait Sealed {}
+        /// Recursion limit for `indirect` magic
+        #[doc(alias = "MAGIC_PARAM_INDIR_MAX")]
+        pub fn indir_max(&self) -> usize {
+            self.indir_max
+        }
 
-        impl Sealed for super::Open {}
-        impl Sealed for super::Load {}
-    }
+        /// Sets the recursion limit for `indirect` magic
+        pub fn set_indir_max(&mut self, value: usize) -> &mut Self {
+            self.indir_max = value;
+            self
+        }
 
-    #[doc(hidden)]
-    pub trait State: private::Sealed {}
+        /// Use count limit for `name`/`use` magic
+        #[doc(alias = "MAGIC_PARAM_NAME_MAX")]
+        pub fn name_max(&self) -> usize {
+            self.name_max
+        }
 
-    impl State for Open {}
-    impl State for Load {}
+        /// Sets the use count limit for `name`/`use` magic
+        pub fn set_name_max(&mut self, value: usize) -> &mut Self {
+            self.name_max = value;
+            self
+        }
 
-    #[derive(Debug)]
-    pub struct Cookie<S: State> {
-        cookie: crate::magic::Cookie,
-        marker: std::marker::PhantomData<S>,
-    }
+        /// Maximum number of ELF program sections processed
+        #[doc(alias = "MAGIC_PARAM_ELF_PHNUM_MAX")]
+        pub fn elf_phnum_max(&self) -> usize {
+            self.elf_phnum_max
+        }
 
-    #[derive(thiserror::Error, Debug)]
-    #[error("magic cookie error in `libmagic` function {}", .function)]
-    pub struct LoadError<S: State> {
-        function: &'static str,
-        //#[backtrace]
-        source: crate::magic::CookieError,
-        cookie: Cookie<S>,
-    }
+        /// Sets the maximum number of ELF program sections processed
+        pub fn set_elf_phnum_max(&mut self, value: usize) -> &mut Self {
+            self.elf_phnum_max = value;
+            self
+        }
 
-    impl<S: State> LoadError<S> {
-        /// Returns the cookie in its original state
-        pub fn cookie(self) -> Cookie<S> {
-            self.cookie
+        /// Maximum number of ELF sections processed
+        #[doc(alias = "MAGIC_PARAM_ELF_SHNUM_MAX")]
+        pub fn elf_shnum_max(&self) -> usize {
+            self.elf_shnum_max
         }
-    }
 
-    impl<S: State> Drop for Cookie<S> {
-        /// Closes the loaded magic database files and deallocates any resources used
-        #[doc(alias = "magic_close")]
-        fn drop(&mut self) {
-            crate::magic::close(&mut self.cookie);
+        /// Sets the maximum number of ELF sections processed
+        pub fn set_elf_shnum_max(&mut self, value: usize) -> &mut Self {
+            self.elf_shnum_max = value;
+            self
         }
-    }
 
-    /// Operations that are valid in the `Open` state
-    ///
-    /// A new cookie created with [`Cookie::open`](Cookie::open) does not have any databases [loaded](Cookie::load).
-    impl Cookie<Open> {
-        pub fn open(flags: Flags) -> Result<Cookie<Open>, OpenError> {
-            match crate::magic::open(flags.bits() as _) {
-                Err(err) => Err(OpenError {
-                    flags,
-                    kind: match err.errno().kind() {
-                        std::io::ErrorKind::InvalidInput => OpenErrorKind::UnsupportedFlags,
-                        _ => OpenErrorKind::Errno,
-                    },
-                    source: err,
-                }),
-                Ok(cookie) => {
-                    let cookie = Cookie {
-                        cookie,
-                        marker: std::marker::PhantomData,
-                    };
-                    Ok(cookie)
-                }
-            }
+        /// Maximum number of ELF notes processed
+        #[doc(alias = "MAGIC_PARAM_ELF_NOTES_MAX")]
+        pub fn elf_notes_max(&self) -> usize {
+            self.elf_notes_max
         }
-    }
 
-    impl Cookie<Load> {
-        pub fn file<P: AsRef<Path>>(&self, filename: P) -> Result<String, Error> {
-            let c_string = CString::new(filename.as_ref().to_string_lossy().into_owned()).unwrap();
-            match crate::magic::file(&self.cookie, c_string.as_c_str()) {
-                Ok(res) => Ok(res.to_string_lossy().to_string()),
-                Err(err) => Err(Error {
-                    function: "magic_file",
-                    source: err,
-                }),
-            }
+        /// Sets the maximum number of ELF notes processed
+        pub fn set_elf_notes_max(&mut self, value: usize) -> &mut Self {
+            self.elf_notes_max = value;
+            self
         }
-        pub fn buffer(&self, buffer: &[u8]) -> Result<String, Error> {
-            match crate::magic::buffer(&self.cookie, buffer) {
-                Ok(res) => Ok(res.to_string_lossy().to_string()),
-                Err(err) => Err(Error {
-                    function: "magic_buffer",
-                    source: err,
-                }),
-            }
+
+        /// Length limit for regex searches
+        #[doc(alias = "MAGIC_PARAM_REGEX_MAX")]
+        pub fn regex_max(&self) -> usize {
+            self.regex_max
         }
-    }
 
-    /// Operations that are valid in any state
-    impl<S: State> Cookie<S> {
-        pub fn load(self, filenames: &DatabasePaths) -> Result<Cookie<Load>, LoadError<S>> {
-            match crate::magic::load(&self.cookie, filenames.filenames.as_deref()) {
-                Err(err) => Err(LoadError {
-                    function: "magic_load",
-                    source: err,
-                    cookie: self,
-                }),
-                Ok(_) => {
-                    let mut cookie = std::mem::ManuallyDrop::new(self);
+        /// Sets the length limit for regex searches
+        pub fn set_regex_max(&mut self, value: usize) -> &mut Self {
+            self.regex_max = value;
+            self
+        }
 
-                    let cookie = Cookie {
-                        cookie: crate::magic::Cookie::new(&mut cookie.cookie),
-                        marker: std::marker::PhantomData,
-                    };
-                    Ok(cookie)
-                }
-            }
+        /// Maximum number of bytes to read from each input
+        #[doc(alias = "MAGIC_PARAM_BYTES_MAX")]
+        pub fn bytes_max(&self) -> usize {
+            self.bytes_max
         }
-        pub fn load_buffers(self, buffers: &[&[u8]]) -> Result<Cookie<Load>, LoadError<S>> {
-            match crate::magic::load_buffers(&self.cookie, buffers) {
-                Err(err) => Err(LoadError {
-                    function: "magic_load_buffers",
-                    source: err,
-                    cookie: self,
-                }),
-                Ok(_) => {
-                    let mut cookie = std::mem::ManuallyDrop::new(self);
 
-                    let cookie = Cookie {
-                        cookie: crate::magic::Cookie::new(&mut cookie.cookie),
-                        marker: std::marker::PhantomData,
-                    };
-                    Ok(cookie)
-                }
-            }
+        /// Sets the maximum number of bytes to read from each input
+        pub fn set_bytes_max(&mut self, value: usize) -> &mut Self {
+            self.bytes_max = value;
+            self
         }
-        pub fn set_flags(&self, flags: Flags) -> Result<(), SetFlagsError> {
-            let ret = crate::magic::setflags(&self.cookie, flags.bits() as _);
-            match ret {
-                // according to `libmagic` man page this is the only flag that could be unsupported
-                Err(err) => Err(SetFlagsError {
-                    flags: Flags::PRESERVE_ATIME,
-                    source: err,
-                }),
-                Ok(_) => Ok(()),
-            }
+
+        /// Maximum number of bytes to scan for encoding detection
+        #[doc(alias = "MAGIC_PARAM_ENCODING_MAX")]
+        pub fn encoding_max(&self) -> usize {
+            self.encoding_max
         }
 
-        pub fn compile(&self, filenames: &DatabasePaths) -> Result<(), Error> {
-            match crate::magic::compile(&self.cookie, filenames.filenames.as_deref()) {
-                Err(err) => Err(Error {
-                    function: "magic_compile",
-                    source: err,
-                }),
-                Ok(_) => Ok(()),
-            }
+        /// Sets the maximum number of bytes to scan for encoding detection
+        pub fn set_encoding_max(&mut self, value: usize) -> &mut Self {
+            self.encoding_max = value;
+            self
         }
 
-        pub fn check(&self, filenames: &DatabasePaths) -> Result<(), Error> {
-            match crate::magic::check(&self.cookie, filenames.filenames.as_deref()) {
-                Err(err) => Err(Error {
-                    function: "magic_check",
-                    source: err,
-                }),
-                Ok(_) => Ok(()),
-            }
+        /// Maximum size of an ELF section processed, `None` if the linked `libmagic` has no such limit
+        #[doc(alias = "MAGIC_PARAM_ELF_SHSIZE_MAX")]
+        pub fn elf_shsize_max(&self) -> Option<usize> {
+            self.elf_shsize_max
         }
 
-        pub fn list(&self, filenames: &DatabasePaths) -> Result<(), Error> {
-            match crate::magic::list(&self.cookie, filenames.filenames.as_deref()) {
-                Err(err) => Err(Error {
-                    function: "magic_list",
+        /// Sets the maximum size of an ELF section processed
+        pub fn set_elf_shsize_max(&mut self, value: usize) -> &mut Self {
+            self.elf_shsize_max = Some(value);
+            self
+        }
+
+        /// Name, parameter, value and largest value `libmagic` can store of each limit
+        /// supported by the linked `libmagic`
+        ///
+        /// See `magic_setparam()` in `magic.c`, which truncates all but the `size_t` limits to `uint16_t`.
+        fn params(
+            &self,
+        ) -> impl Iterator<Item = (&'static str, libc::c_uint, Option<usize>, usize)> {
+            const U16: usize = u16::MAX as usize;
+            [
+                (
+                    "MAGIC_PARAM_INDIR_MAX",
+                    magic::MAGIC_PARAM_INDIR_MAX,
+                    Some(self.indir_max),
+                    U16,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_NAME_MAX",
+                    magic::MAGIC_PARAM_NAME_MAX,
+                    Some(self.name_max),
+                    U16,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_ELF_PHNUM_MAX",
+                    magic::MAGIC_PARAM_ELF_PHNUM_MAX,
+                    Some(self.elf_phnum_max),
+                    U16,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_ELF_SHNUM_MAX",
+                    magic::MAGIC_PARAM_ELF_SHNUM_MAX,
+                    Some(self.elf_shnum_max),
+                    U16,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_ELF_NOTES_MAX",
+                    magic::MAGIC_PARAM_ELF_NOTES_MAX,
+                    Some(self.elf_notes_max),
+                    U16,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_REGEX_MAX",
+                    magic::MAGIC_PARAM_REGEX_MAX,
+                    Some(self.regex_max),
+                    U16,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_BYTES_MAX",
+                    magic::MAGIC_PARAM_BYTES_MAX,
+                    Some(self.bytes_max),
+                    usize::MAX,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_ENCODING_MAX",
+                    magic::MAGIC_PARAM_ENCODING_MAX,
+                    Some(self.encoding_max),
+                    usize::MAX,
+                    0,
+                ),
+                (
+                    "MAGIC_PARAM_ELF_SHSIZE_MAX",
+                    magic::MAGIC_PARAM_ELF_SHSIZE_MAX,
+                    self.elf_shsize_max,
+                    usize::MAX,
+                    545,
+                ),
+            ]
+            .into_iter()
+            .filter(|(_, _, _, _, since)| crate::magic::version() >= *since)
+            .map(|(name, param, value, max, _)| (name, param, value, max))
+        }
+
+        fn set_param(&mut self, param: libc::c_uint, value: usize) {
+            let field = match param {
+                magic::MAGIC_PARAM_INDIR_MAX => &mut self.indir_max,
+                magic::MAGIC_PARAM_NAME_MAX => &mut self.name_max,
+                magic::MAGIC_PARAM_ELF_PHNUM_MAX => &mut self.elf_phnum_max,
+                magic::MAGIC_PARAM_ELF_SHNUM_MAX => &mut self.elf_shnum_max,
+                magic::MAGIC_PARAM_ELF_NOTES_MAX => &mut self.elf_notes_max,
+                magic::MAGIC_PARAM_REGEX_MAX => &mut self.regex_max,
+                magic::MAGIC_PARAM_BYTES_MAX => &mut self.bytes_max,
+                magic::MAGIC_PARAM_ENCODING_MAX => &mut self.encoding_max,
+                magic::MAGIC_PARAM_ELF_SHSIZE_MAX => {
+                    self.elf_shsize_max = Some(value);
+                    return;
+                }
+                _ => unreachable!("unknown `libmagic` parameter {}", param),
+            };
+            *field = value;
+        }
+    }
+
+    /// Combined result of [`Cookie::identify_file()`](Cookie::identify_file) and [`Cookie::identify_buffer()`](Cookie::identify_buffer)
+    ///
+    /// Gathers what `libmagic` reports with the `MIME_TYPE`, `MIME_ENCODING`, `EXTENSION` and `APPLE` flags
+    /// next to the textual description, so callers do not need to switch flags and parse each output themselves.
+    #[derive(PartialEq, Eq, Hash, Debug, Clone)]
+    pub struct Identification {
+        description: String,
+        mime_type: String,
+        encoding: String,
+        extensions: Vec<String>,
+        apple_creator: Option<String>,
+        apple_type: Option<String>,
+    }
+
+    impl Identification {
+        /// Combines the outputs of the passes without output flags, with `MIME`, `EXTENSION` and `APPLE`
+        fn new(description: String, mime: &str, extensions: String, apple: String) -> Self {
+            // "text/plain; charset=us-ascii", the encoding is left empty if there is no charset
+            let (mime_type, encoding) = match mime.split_once(';') {
+                Some((mime_type, parameters)) => {
+                    let parameters = parameters.trim();
+                    let encoding = parameters.strip_prefix("charset=").unwrap_or(parameters);
+                    (mime_type.trim().to_string(), encoding.to_string())
+                }
+                None => (mime.trim().to_string(), String::new()),
+            };
+            // `libmagic` reports "???" for unknown extensions and "UNKNUNKN" for unknown Apple creator/type
+            let extensions = match extensions.as_str() {
+                "???" => Vec::new(),
+                _ => extensions
+                    .split('/')
+                    .filter(|e| !e.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            };
+            let apple_part = |part: Option<&str>| match part {
+                Some("UNKN") | Some("????") | None => None,
+                Some(part) => Some(part.to_string()),
+            };
+            let (apple_creator, apple_type) = match apple.len() == 8 && apple.is_char_boundary(4) {
+                true => (apple_part(apple.get(..4)), apple_part(apple.get(4..))),
+                _ => (None, None),
+            };
+
+            Self {
+                description,
+                mime_type,
+                encoding,
+                extensions,
+                apple_creator,
+                apple_type,
+            }
+        }
+
+        /// Textual description, as without any output flags
+        pub fn description(&self) -> &str {
+            &self.description
+        }
+
+        /// MIME type, e.g. `text/plain`
+        pub fn mime_type(&self) -> &str {
+            &self.mime_type
+        }
+
+        /// MIME encoding (charset), e.g. `us-ascii` or `binary`
+        pub fn encoding(&self) -> &str {
+            &self.encoding
+        }
+
+        /// Common file name extensions without leading dot, empty if unknown
+        pub fn extensions(&self) -> &[String] {
+            &self.extensions
+        }
+
+        /// Apple creator code, if known
+        pub fn apple_creator(&self) -> Option<&str> {
+            self.apple_creator.as_deref()
+        }
+
+        /// Apple type code, if known
+        pub fn apple_type(&self) -> Option<&str> {
+            self.apple_type.as_deref()
+        }
+    }
+
+    /// Single match from [`Cookie::all_matches()`](Cookie::all_matches), strongest first
+    #[derive(PartialEq, Eq, Hash, Debug, Clone)]
+    pub struct Match {
+        description: String,
+        mime_type: Option<String>,
+    }
+
+    impl Match {
+        /// Splits the `CONTINUE` output of a description pass and a MIME type pass into matches
+        fn split(descriptions: &str, mime_types: &str) -> Vec<Match> {
+            const SEPARATOR: &str = "\n- ";
+
+            let mime_types: Vec<&str> = mime_types.split(SEPARATOR).collect();
+            let descriptions: Vec<&str> = descriptions.split(SEPARATOR).collect();
+            // matches without a MIME type print an empty entry, but trailing ones are left out,
+            // so the types can only be attributed to each match if the counts agree
+            let aligned = mime_types.len() == descriptions.len();
+
+            descriptions
+                .into_iter()
+                .enumerate()
+                .map(|(i, description)| Match {
+                    description: description.to_string(),
+                    mime_type: mime_types
+                        .get(i)
+                        .filter(|m| aligned && !m.is_empty())
+                        .map(|m| m.to_string()),
+                })
+                .collect()
+        }
+
+        /// Textual description of this match
+        pub fn description(&self) -> &str {
+            &self.description
+        }
+
+        /// MIME type of this match, if it could be attributed
+        pub fn mime_type(&self) -> Option<&str> {
+            self.mime_type.as_deref()
+        }
+    }
+
+    /// Kind of patterns a [`MagicEntry`] belongs to
+    #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+    pub enum PatternKind {
+        /// Matched against binary content
+        Binary,
+        /// Matched against text content
+        Text,
+    }
+
+    /// Entry of a loaded database as reported by [`Cookie::list()`](Cookie::list)
+    ///
+    /// `magic_list()` does not report extension hints or source file names,
+    /// `line` is the line number within the source file the entry was compiled from.
+    #[derive(PartialEq, Eq, Hash, Debug, Clone)]
+    pub struct MagicEntry {
+        set: usize,
+        kind: PatternKind,
+        strength: usize,
+        line: u32,
+        description: String,
+        mime_type: Option<String>,
+    }
+
+    impl MagicEntry {
+        /// Parses the `magic_list()` output, i.e. `Set <n>:` and `Binary patterns:`/`Text patterns:` headers
+        /// followed by lines of the form `Strength = <strength>@<line>: <description> [<mime type>]`
+        #[cfg_attr(not(unix), allow(dead_code))]
+        fn parse_list(output: &str) -> Vec<MagicEntry> {
+            let mut set = 0;
+            let mut kind = PatternKind::Binary;
+            let mut entries = Vec::new();
+
+            for line in output.lines() {
+                if let Some(n) = line.strip_prefix("Set ").and_then(|l| l.strip_suffix(':')) {
+                    set = n.trim().parse().unwrap_or(set);
+                } else if line == "Binary patterns:" {
+                    kind = PatternKind::Binary;
+                } else if line == "Text patterns:" {
+                    kind = PatternKind::Text;
+                } else if let Some(entry) = Self::parse_line(set, kind, line) {
+                    entries.push(entry);
+                }
+            }
+            entries
+        }
+
+        #[cfg_attr(not(unix), allow(dead_code))]
+        fn parse_line(set: usize, kind: PatternKind, line: &str) -> Option<MagicEntry> {
+            let line = line.strip_prefix("Strength = ")?;
+            let (strength, line) = line.split_once('@')?;
+            let (lineno, line) = line.split_once(": ")?;
+            let (description, mime_type) = line.strip_suffix(']')?.rsplit_once(" [")?;
+
+            Some(MagicEntry {
+                set,
+                kind,
+                strength: strength.trim().parse().ok()?,
+                line: lineno.parse().ok()?,
+                description: description.to_string(),
+                mime_type: match mime_type.is_empty() {
+                    true => None,
+                    _ => Some(mime_type.to_string()),
+                },
+            })
+        }
+
+        /// Index of the magic set this entry belongs to
+        pub fn set(&self) -> usize {
+            self.set
+        }
+
+        /// Whether this entry matches binary or text content
+        pub fn kind(&self) -> PatternKind {
+            self.kind
+        }
+
+        /// Strength `libmagic` orders entries by
+        pub fn strength(&self) -> usize {
+            self.strength
+        }
+
+        /// Line number of this entry in its source file
+        pub fn line(&self) -> u32 {
+            self.line
+        }
+
+        /// Description (message) printed when this entry matches, may be empty
+        pub fn description(&self) -> &str {
+            &self.description
+        }
+
+        /// MIME type of this entry, if it has one
+        pub fn mime_type(&self) -> Option<&str> {
+            self.mime_type.as_deref()
+        }
+    }
+
+    /// Severity of a [`Diagnostic`]
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub enum Severity {
+        /// The entry is skipped or interpreted in a possibly unintended way
+        Warning,
+        /// The files could not be used at all
+        Error,
+    }
+
+    /// Finding about magic source files reported by [`Cookie::check()`](Cookie::check)
+    #[derive(PartialEq, Eq, Hash, Debug, Clone)]
+    pub struct Diagnostic {
+        path: Option<PathBuf>,
+        line: Option<u32>,
+        severity: Severity,
+        message: String,
+    }
+
+    impl Diagnostic {
+        /// Parses warnings `libmagic` printed, i.e. lines of the form `<path>, <line>: Warning: <message>`
+        /// or just `Warning: <message>`, skipping anything else such as entry dumps
+        fn parse_warnings(output: &str) -> Vec<Diagnostic> {
+            output
+                .lines()
+                .filter_map(|line| {
+                    let (path, line, message) = match line.strip_prefix("Warning: ") {
+                        Some(message) => (None, None, message),
+                        None => {
+                            let (location, message) = line.split_once(": Warning: ")?;
+                            let (path, line) = location.rsplit_once(", ")?;
+                            (Some(PathBuf::from(path)), line.parse().ok(), message)
+                        }
+                    };
+                    Some(Diagnostic {
+                        path,
+                        line,
+                        severity: Severity::Warning,
+                        message: message.to_string(),
+                    })
+                })
+                .collect()
+        }
+
+        /// Source file the finding is about, if known
+        pub fn path(&self) -> Option<&Path> {
+            self.path.as_deref()
+        }
+
+        /// Line number within [`path`](Diagnostic::path), if known
+        pub fn line(&self) -> Option<u32> {
+            self.line
+        }
+
+        /// Severity of the finding
+        pub fn severity(&self) -> Severity {
+            self.severity
+        }
+
+        /// Message as printed by `libmagic`
+        pub fn message(&self) -> &str {
+            &self.message
+        }
+    }
+
+    /// Result of [`Cookie::reader()`](Cookie::reader)
+    #[derive(Debug)]
+    pub struct Identified<R> {
+        result: String,
+        prefix: Vec<u8>,
+        reader: R,
+    }
+
+    impl<R> Identified<R> {
+        pub(crate) fn new(result: String, prefix: Vec<u8>, reader: R) -> Self {
+            Self {
+                result,
+                prefix,
+                reader,
+            }
+        }
+
+        /// Textual result, as from [`Cookie::buffer()`](Cookie::buffer)
+        pub fn result(&self) -> &str {
+            &self.result
+        }
+
+        /// Bytes consumed from the reader to identify its content
+        pub fn prefix(&self) -> &[u8] {
+            &self.prefix
+        }
+
+        /// Returns the result, the consumed prefix and the original reader positioned right after it
+        pub fn into_parts(self) -> (String, Vec<u8>, R) {
+            (self.result, self.prefix, self.reader)
+        }
+    }
+
+    impl<R: Read> Identified<R> {
+        /// Returns a reader yielding the consumed prefix followed by the rest of the original reader
+        pub fn into_reader(self) -> std::io::Chain<std::io::Cursor<Vec<u8>>, R> {
+            std::io::Cursor::new(self.prefix).chain(self.reader)
+        }
+    }
+
+    /// Error within [`Cookie::reader()`](Cookie::reader)
+    #[derive(thiserror::Error, Debug)]
+    pub enum ReadError {
+        /// Reading the prefix failed
+        #[error("could not read content to identify")]
+        Io(#[from] std::io::Error),
+        /// Identifying the prefix failed
+        #[error(transparent)]
+        Magic(#[from] Error),
+    }
+
+    /// Error within several [`Cookie`] functions
+    ///
+    /// Most functions on a [`Cookie`] can return an error from `libmagic`,
+    /// which unfortunately is not very structured.
+    #[derive(thiserror::Error, Debug)]
+    #[error("{}",
+        match .source {
+            ErrorSource::Io(err) => format!("I/O error in {}: {}", .function, err),
+            source => format!("magic cookie error in `libmagic` function {} {}", .function, source),
+        }
+    )]
+    pub struct Error {
+        function: &'static str,
+        //#[backtrace]
+        source: ErrorSource,
+    }
+
+    impl Error {
+        /// Name of the `libmagic` function that failed
+        ///
+        /// For I/O errors [`Cookie`] functions run into around `libmagic`, e.g. while staging a compiled database,
+        /// this is the name of the failed file system operation instead.
+        pub fn function(&self) -> &'static str {
+            self.function
+        }
+
+        /// Classification of this error
+        pub fn kind(&self) -> ErrorKind {
+            match &self.source {
+                ErrorSource::Cookie(err) => ErrorKind::classify(self.function, err),
+                ErrorSource::InvalidPath(_) => ErrorKind::InvalidPath,
+                ErrorSource::SetFlags(_) => ErrorKind::UnsupportedFlags,
+                ErrorSource::Io(err) => match err.kind() {
+                    std::io::ErrorKind::NotFound => ErrorKind::FileNotFound,
+                    std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                    std::io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+                    _ => ErrorKind::Io,
+                },
+            }
+        }
+
+        /// Error message set by `libmagic`, if it got that far
+        pub fn explanation(&self) -> Option<&std::ffi::CStr> {
+            match &self.source {
+                ErrorSource::Cookie(err) => Some(&err.explanation),
+                ErrorSource::InvalidPath(_) | ErrorSource::SetFlags(_) | ErrorSource::Io(_) => None,
+            }
+        }
+
+        /// OS error `libmagic` ran into, if any
+        pub fn errno(&self) -> Option<&std::io::Error> {
+            match &self.source {
+                ErrorSource::Cookie(err) => err.errno(),
+                ErrorSource::InvalidPath(_) | ErrorSource::SetFlags(_) => None,
+                ErrorSource::Io(err) => Some(err),
+            }
+        }
+    }
+
+    /// Kind of error reported by the error types of [`Cookie`] functions
+    ///
+    /// Derived from the OS errno and the message `libmagic` sets, see [`Error::kind()`](Error::kind).
+    /// Every error type of [`Cookie`] functions offers the same classification through its `kind()`.
+    #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// The file to identify does not exist
+        FileNotFound,
+        /// A file could not be accessed due to missing permissions
+        PermissionDenied,
+        /// No usable database files were found
+        DatabaseMissing,
+        /// A compiled database was produced by a different `libmagic` version
+        DatabaseVersionMismatch,
+        /// A magic source file or compiled database could not be parsed
+        InvalidMagicSyntax,
+        /// One of the [`Limits`] was hit
+        LimitExceeded,
+        /// A path could not be passed to `libmagic`
+        InvalidPath,
+        /// Flags not supported by the linked `libmagic` were given
+        UnsupportedFlags,
+        /// The operation is not available on this platform
+        Unsupported,
+        /// Any other OS error
+        Io,
+        /// `libmagic` violated its API contract, e.g. failed without setting an error message
+        ApiViolation,
+        /// Anything `libmagic` did not explain any further
+        Unknown,
+    }
+
+    impl ErrorKind {
+        fn classify(function: &str, err: &crate::magic::CookieError) -> ErrorKind {
+            match err.is_api_violation() {
+                true => ErrorKind::ApiViolation,
+                _ => Self::from_error(function, err.errno(), &err.explanation.to_string_lossy()),
+            }
+        }
+
+        /// Classifies an error `libmagic` reported within `function`
+        ///
+        /// An OS error takes precedence, otherwise only messages `libmagic` is known to set are recognized.
+        fn from_error(
+            function: &str,
+            errno: Option<&std::io::Error>,
+            explanation: &str,
+        ) -> ErrorKind {
+            let database_function = function != "magic_file"
+                && function != "magic_buffer"
+                && function != "magic_descriptor";
+
+            if let Some(errno) = errno {
+                return match errno.kind() {
+                    std::io::ErrorKind::NotFound if database_function => ErrorKind::DatabaseMissing,
+                    std::io::ErrorKind::NotFound => ErrorKind::FileNotFound,
+                    std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                    _ => ErrorKind::Io,
+                };
+            }
+
+            // errors while parsing magic sources are prefixed with the line, see `file_magerror()`
+            let (parsing, message) = match explanation.strip_prefix("line ") {
+                Some(rest) => match rest.split_once(':') {
+                    Some((line, message)) if line.bytes().all(|b| b.is_ascii_digit()) => {
+                        (true, message.trim_start())
+                    }
+                    _ => (false, explanation),
+                },
+                None => (false, explanation),
+            };
+
+            if message.starts_with("File ") && message.contains(" supports only version ") {
+                ErrorKind::DatabaseVersionMismatch
+            } else if message.starts_with("could not find any valid magic files") {
+                // `libmagic` says the same for missing files and for files without a single valid entry,
+                // the latter being the common case when checking or compiling sources
+                match function {
+                    "magic_check" | "magic_compile" => ErrorKind::InvalidMagicSyntax,
+                    _ => ErrorKind::DatabaseMissing,
+                }
+            } else if message.starts_with("indirect count (")
+                || message.starts_with("name use count (")
+                || message.starts_with("indirect recursion nesting (")
+            {
+                ErrorKind::LimitExceeded
+            } else if database_function && (parsing || message.starts_with("bad magic in `")) {
+                ErrorKind::InvalidMagicSyntax
+            } else {
+                ErrorKind::Unknown
+            }
+        }
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    enum ErrorSource {
+        #[error("{:?}", .0.explanation)]
+        Cookie(#[from] crate::magic::CookieError),
+        #[error("invalid path, it contains a NUL byte")]
+        InvalidPath(#[from] std::ffi::NulError),
+        #[error("{}", .0)]
+        SetFlags(#[from] crate::magic::SetFlagsError),
+        #[error("{}", .0)]
+        Io(#[from] std::io::Error),
+    }
+
+    #[doc(hidden)]
+    #[derive(Debug)]
+    pub enum Open {}
+
+    #[doc(hidden)]
+    #[derive(Debug)]
+    pub enum Load {}
+
+    mod private {
+        pub trait Sealed {}
+
+        impl Sealed for super::Open {}
+        impl Sealed for super::Load {}
+    }
+
+    #[doc(hidden)]
+    pub trait State: private::Sealed {}
+
+    impl State for Open {}
+    impl State for Load {}
+
+    #[derive(Debug)]
+    pub struct Cookie<S: State> {
+        cookie: crate::magic::Cookie,
+        /// In-memory databases `libmagic` keeps pointers into, see [`Cookie::load_buffers()`](Cookie::load_buffers)
+        buffers: Vec<DatabaseBuffer>,
+        diagnostics: Diagnostics,
+        marker: std::marker::PhantomData<S>,
+    }
+
+    /// Where a [`Cookie`] routes the diagnostics `libmagic` prints with [`Flags::DEBUG`] or [`Flags::CHECK`]
+    ///
+    /// Capturing temporarily redirects the process-wide stderr during each call,
+    /// so anything other threads print to stderr meanwhile is captured as well,
+    /// and spawns a thread draining the redirected output for each call.
+    /// It is only available on unix, elsewhere diagnostics always go to stderr.
+    #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default)]
+    pub enum DiagnosticsMode {
+        /// Leave them on the process's stderr
+        #[default]
+        Inherit,
+        /// Forward each line to the `log` crate with target `libmagic::debug`,
+        /// or `libmagic::check` at warning level for warnings about magic entries
+        Log,
+        /// Keep the lines until [`Cookie::take_diagnostics()`](Cookie::take_diagnostics) is called
+        Collect,
+    }
+
+    #[derive(Debug, Default)]
+    struct Diagnostics {
+        mode: std::cell::Cell<DiagnosticsMode>,
+        collected: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl Diagnostics {
+        #[cfg(unix)]
+        fn dispatch(&self, mode: DiagnosticsMode, output: &[u8]) {
+            let lines = String::from_utf8_lossy(output);
+            let lines = lines.lines().filter(|line| !line.is_empty());
+            match mode {
+                // output captured regardless of the mode goes where it would have gone anyway
+                DiagnosticsMode::Inherit => {
+                    let _ = std::io::Write::write_all(&mut std::io::stderr(), output);
+                }
+                DiagnosticsMode::Log => {
+                    for line in lines {
+                        match line.contains("Warning:") {
+                            true => log::warn!(target: "libmagic::check", "{}", line),
+                            _ => log::debug!(target: "libmagic::debug", "{}", line),
+                        }
+                    }
+                }
+                DiagnosticsMode::Collect => self
+                    .collected
+                    .borrow_mut()
+                    .extend(lines.map(str::to_string)),
+            }
+        }
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("magic cookie error in `libmagic` function {}", .function)]
+    pub struct LoadError<S: State> {
+        function: &'static str,
+        //#[backtrace]
+        source: crate::magic::CookieError,
+        cookie: Cookie<S>,
+    }
+
+    impl<S: State> LoadError<S> {
+        /// Returns the cookie in its original state
+        pub fn cookie(self) -> Cookie<S> {
+            self.cookie
+        }
+
+        /// Name of the `libmagic` function that failed
+        ///
+        /// For I/O errors [`Cookie`] functions run into around `libmagic`, e.g. while staging a compiled database,
+        /// this is the name of the failed file system operation instead.
+        pub fn function(&self) -> &'static str {
+            self.function
+        }
+
+        /// Classification of this error
+        pub fn kind(&self) -> ErrorKind {
+            ErrorKind::classify(self.function, &self.source)
+        }
+
+        /// Error message set by `libmagic`
+        pub fn explanation(&self) -> &std::ffi::CStr {
+            &self.source.explanation
+        }
+
+        /// OS error `libmagic` ran into, if any
+        pub fn errno(&self) -> Option<&std::io::Error> {
+            self.source.errno()
+        }
+    }
+
+    impl<S: State> Drop for Cookie<S> {
+        /// Closes the loaded magic database files and deallocates any resources used
+        #[doc(alias = "magic_close")]
+        fn drop(&mut self) {
+            crate::magic::close(&mut self.cookie);
+        }
+    }
+
+    /// Operations that are valid in the `Open` state
+    ///
+    /// A new cookie created with [`Cookie::open`](Cookie::open) does not have any databases [loaded](Cookie::load).
+    impl Cookie<Open> {
+        pub fn open(flags: Flags) -> Result<Cookie<Open>, OpenError> {
+            match crate::magic::open(flags.bits() as _) {
+                Err(err) => Err(OpenError {
+                    flags,
+                    kind: match err.errno().kind() {
+                        std::io::ErrorKind::InvalidInput => OpenErrorKind::UnsupportedFlags,
+                        _ => OpenErrorKind::Errno,
+                    },
+                    source: err,
+                }),
+                Ok(cookie) => {
+                    let cookie = Cookie {
+                        cookie,
+                        buffers: Vec::new(),
+                        diagnostics: Diagnostics::default(),
+                        marker: std::marker::PhantomData,
+                    };
+                    Ok(cookie)
+                }
+            }
+        }
+    }
+
+    impl Cookie<Load> {
+        pub fn file<P: AsRef<Path>>(&self, filename: P) -> Result<String, Error> {
+            self.file_bytes(filename)
+                .map(|res| res.to_string_lossy().into_owned())
+        }
+
+        /// Like [`Cookie::file()`](Cookie::file), but returns the result exactly as `libmagic` produced it
+        ///
+        /// The path is passed on byte for byte as well, so non-UTF-8 file names are identified faithfully.
+        pub fn file_bytes<P: AsRef<Path>>(&self, filename: P) -> Result<CString, Error> {
+            let c_string = path_to_cstring(filename.as_ref()).map_err(|err| Error {
+                function: "magic_file",
+                source: err.into(),
+            })?;
+            match self.diagnose(|| crate::magic::file(&self.cookie, c_string.as_c_str())) {
+                Ok(res) => Ok(res),
+                Err(err) => Err(Error {
+                    function: "magic_file",
+                    source: err.into(),
+                }),
+            }
+        }
+
+        /// Like [`Cookie::file()`](Cookie::file), but with `flags` in effect for this call only
+        ///
+        /// The previous flags are restored afterwards, also when identification fails.
+        pub fn file_with<P: AsRef<Path>>(
+            &self,
+            filename: P,
+            flags: Flags,
+        ) -> Result<String, Error> {
+            let filename = filename.as_ref();
+            self.with_flags(flags, || self.file(filename))
+        }
+
+        /// Identifies the content of an already open file descriptor
+        ///
+        /// Accepts anything that can lend its descriptor, e.g. a [`File`](std::fs::File),
+        /// [`Stdin`](std::io::Stdin), pipe ends, sockets or an [`OwnedFd`](std::os::fd::OwnedFd) from `memfd_create`.
+        ///
+        /// `libmagic` reads from the current position of the descriptor and does not close it.
+        /// Seekable descriptors are rewound to their previous offset afterwards,
+        /// whereas pipes and sockets are consumed.
+        #[cfg(unix)]
+        #[doc(alias = "magic_descriptor")]
+        pub fn descriptor<F: std::os::fd::AsFd>(&self, fd: F) -> Result<String, Error> {
+            self.descriptor_bytes(fd)
+                .map(|res| res.to_string_lossy().into_owned())
+        }
+
+        /// Like [`Cookie::descriptor()`](Cookie::descriptor), but returns the result exactly as `libmagic` produced it
+        #[cfg(unix)]
+        pub fn descriptor_bytes<F: std::os::fd::AsFd>(&self, fd: F) -> Result<CString, Error> {
+            use std::os::fd::AsRawFd;
+
+            let fd = fd.as_fd();
+            match self.diagnose(|| crate::magic::descriptor(&self.cookie, fd.as_raw_fd())) {
+                Ok(res) => Ok(res),
+                Err(err) => Err(Error {
+                    function: "magic_descriptor",
+                    source: err.into(),
+                }),
+            }
+        }
+
+        pub fn buffer(&self, buffer: &[u8]) -> Result<String, Error> {
+            self.buffer_bytes(buffer)
+                .map(|res| res.to_string_lossy().into_owned())
+        }
+
+        /// Like [`Cookie::buffer()`](Cookie::buffer), but returns the result exactly as `libmagic` produced it
+        pub fn buffer_bytes(&self, buffer: &[u8]) -> Result<CString, Error> {
+            match self.diagnose(|| crate::magic::buffer(&self.cookie, buffer)) {
+                Ok(res) => Ok(res),
+                Err(err) => Err(Error {
+                    function: "magic_buffer",
+                    source: err.into(),
+                }),
+            }
+        }
+
+        /// Like [`Cookie::buffer()`](Cookie::buffer), but with `flags` in effect for this call only
+        ///
+        /// The previous flags are restored afterwards, also when identification fails.
+        pub fn buffer_with(&self, buffer: &[u8], flags: Flags) -> Result<String, Error> {
+            self.with_flags(flags, || self.buffer(buffer))
+        }
+
+        /// Identifies the content of `reader` without reading all of it
+        ///
+        /// Only as many bytes as `libmagic` would look at (see [`Limits::bytes_max()`](Limits::bytes_max)) are read.
+        /// The consumed prefix is handed back along with the result,
+        /// so the stream can be passed on unchanged via [`Identified::into_reader()`](Identified::into_reader).
+        pub fn reader<R: std::io::Read>(&self, mut reader: R) -> Result<Identified<R>, ReadError> {
+            let bytes_max = crate::magic::getparam(&self.cookie, magic::MAGIC_PARAM_BYTES_MAX as _)
+                .unwrap_or(Limits::default().bytes_max);
+
+            let mut prefix = Vec::new();
+            reader
+                .by_ref()
+                .take(bytes_max as u64)
+                .read_to_end(&mut prefix)?;
+            let result = self.buffer(&prefix)?;
+
+            Ok(Identified::new(result, prefix, reader))
+        }
+
+        /// Identifies the file at `filename` with all outputs combined, see [`Identification`]
+        pub fn identify_file<P: AsRef<Path>>(&self, filename: P) -> Result<Identification, Error> {
+            let filename = filename.as_ref();
+            self.identify(|cookie| cookie.file(filename))
+        }
+
+        /// Identifies `buffer` with all outputs combined, see [`Identification`]
+        pub fn identify_buffer(&self, buffer: &[u8]) -> Result<Identification, Error> {
+            self.identify(|cookie| cookie.buffer(buffer))
+        }
+
+        /// Returns every match for the file at `filename`, as with [`Flags::CONTINUE`], see [`Match`]
+        pub fn all_matches<P: AsRef<Path>>(&self, filename: P) -> Result<Vec<Match>, Error> {
+            let filename = filename.as_ref();
+            self.matches(|cookie| cookie.file(filename))
+        }
+
+        /// Returns every match for `buffer`, as with [`Flags::CONTINUE`], see [`Match`]
+        pub fn all_matches_buffer(&self, buffer: &[u8]) -> Result<Vec<Match>, Error> {
+            self.matches(|cookie| cookie.buffer(buffer))
+        }
+
+        fn identify<F>(&self, identify: F) -> Result<Identification, Error>
+        where
+            F: Fn(&Self) -> Result<String, Error>,
+        {
+            self.passes(identify, |pass, base| {
+                let single = base - Flags::CONTINUE;
+                Ok(Identification::new(
+                    pass(base)?,
+                    &pass(single | Flags::MIME)?,
+                    pass(single | Flags::EXTENSION)?,
+                    pass(single | Flags::APPLE)?,
+                ))
+            })
+        }
+
+        fn matches<F>(&self, identify: F) -> Result<Vec<Match>, Error>
+        where
+            F: Fn(&Self) -> Result<String, Error>,
+        {
+            self.passes(identify, |pass, base| {
+                // `RAW` keeps the separator between matches a literal newline instead of `\012`
+                let all = base | Flags::CONTINUE | Flags::RAW;
+                Ok(Match::split(&pass(all)?, &pass(all | Flags::MIME_TYPE)?))
+            })
+        }
+
+        /// Runs `passes` with a function identifying the input under the given flags,
+        /// starting from the current flags without any output bits, and restores the cookie flags afterwards
+        fn passes<T, F, P>(&self, identify: F, passes: P) -> Result<T, Error>
+        where
+            F: Fn(&Self) -> Result<String, Error>,
+            P: FnOnce(&dyn Fn(Flags) -> Result<String, Error>, Flags) -> Result<T, Error>,
+        {
+            let restore = RestoreFlags::new(&self.cookie);
+            let base = Flags::from_bits_retain(restore.previous as _) - Flags::NODESC;
+
+            let pass = |flags: Flags| {
+                // only output bits change, so `libmagic` accepts these whenever it accepted the previous flags
+                let _ = crate::magic::setflags(&self.cookie, flags.bits() as _);
+                identify(self)
+            };
+            passes(&pass, base)
+        }
+
+        /// Runs `f` with `flags` set, restoring the previous flags afterwards
+        fn with_flags<T>(
+            &self,
+            flags: Flags,
+            f: impl FnOnce() -> Result<T, Error>,
+        ) -> Result<T, Error> {
+            let _restore = RestoreFlags::new(&self.cookie);
+            crate::magic::setflags(&self.cookie, flags.bits() as _).map_err(|err| Error {
+                function: "magic_setflags",
+                source: err.into(),
+            })?;
+            f()
+        }
+    }
+
+    /// Restores the flags a cookie had on creation when dropped, even while unwinding
+    struct RestoreFlags<'a> {
+        cookie: &'a crate::magic::Cookie,
+        previous: libc::c_int,
+    }
+
+    impl<'a> RestoreFlags<'a> {
+        fn new(cookie: &'a crate::magic::Cookie) -> Self {
+            Self {
+                cookie,
+                previous: crate::magic::getflags(cookie),
+            }
+        }
+    }
+
+    impl Drop for RestoreFlags<'_> {
+        fn drop(&mut self) {
+            // `libmagic` accepted these flags before, so it accepts them again
+            let _ = crate::magic::setflags(self.cookie, self.previous);
+        }
+    }
+
+    /// Operations that are valid in any state
+    impl<S: State> Cookie<S> {
+        pub fn load(self, filenames: &DatabasePaths) -> Result<Cookie<Load>, LoadError<S>> {
+            match self.diagnose(|| crate::magic::load(&self.cookie, filenames.filenames.as_deref()))
+            {
+                Err(err) => Err(LoadError {
+                    function: "magic_load",
+                    source: err,
+                    cookie: self,
+                }),
+                Ok(_) => {
+                    let mut cookie = std::mem::ManuallyDrop::new(self);
+                    // the previous database is gone, so are the pointers into any buffers it was loaded from
+                    drop(std::mem::take(&mut cookie.buffers));
+
+                    let cookie = Cookie {
+                        cookie: crate::magic::Cookie::new(&mut cookie.cookie),
+                        buffers: Vec::new(),
+                        diagnostics: std::mem::take(&mut cookie.diagnostics),
+                        marker: std::marker::PhantomData,
+                    };
+                    Ok(cookie)
+                }
+            }
+        }
+
+        /// Loads compiled databases from memory
+        ///
+        /// `libmagic` keeps pointing into the buffers instead of copying them,
+        /// so the returned cookie holds on to them until it is dropped or loads another database.
+        /// Share one [`DatabaseBuffer`] among many cookies by cloning it, which does not copy the data.
+        pub fn load_buffers<I, B>(self, buffers: I) -> Result<Cookie<Load>, LoadError<S>>
+        where
+            I: IntoIterator<Item = B>,
+            B: Into<DatabaseBuffer>,
+        {
+            let buffers: Vec<DatabaseBuffer> = buffers.into_iter().map(Into::into).collect();
+            let slices: Vec<&[u8]> = buffers.iter().map(DatabaseBuffer::as_bytes).collect();
+
+            match self.diagnose(|| crate::magic::load_buffers(&self.cookie, &slices)) {
+                Err(err) => {
+                    let mut cookie = self;
+                    // a failed load may still have kept pointers into the new buffers
+                    cookie.buffers.extend(buffers);
+                    Err(LoadError {
+                        function: "magic_load_buffers",
+                        source: err,
+                        cookie,
+                    })
+                }
+                Ok(_) => {
+                    let mut cookie = std::mem::ManuallyDrop::new(self);
+                    drop(std::mem::take(&mut cookie.buffers));
+
+                    let cookie = Cookie {
+                        cookie: crate::magic::Cookie::new(&mut cookie.cookie),
+                        buffers,
+                        diagnostics: std::mem::take(&mut cookie.diagnostics),
+                        marker: std::marker::PhantomData,
+                    };
+                    Ok(cookie)
+                }
+            }
+        }
+        /// Loads the database compiled from `file/magic/Magdir` and embedded at build time
+        ///
+        /// The embedded bytes are used in place, so this neither reads any file nor copies the database.
+        #[cfg(feature = "bundled-database")]
+        pub fn load_bundled(self) -> Result<Cookie<Load>, LoadError<S>> {
+            self.load_buffers([DatabaseBuffer::bundled()])
+        }
+
+        /// Sets where `libmagic` diagnostics of later calls on this cookie go
+        ///
+        /// With [`Flags::DEBUG`] or [`Flags::CHECK`] set, `libmagic` prints diagnostics directly to stderr.
+        /// See [`DiagnosticsMode`] for the alternatives.
+        pub fn set_diagnostics(&self, mode: DiagnosticsMode) {
+            self.diagnostics.mode.set(mode);
+        }
+
+        /// Returns where `libmagic` diagnostics of calls on this cookie go
+        pub fn diagnostics(&self) -> DiagnosticsMode {
+            self.diagnostics.mode.get()
+        }
+
+        /// Returns and clears the diagnostics collected in [`DiagnosticsMode::Collect`]
+        pub fn take_diagnostics(&self) -> Vec<String> {
+            self.diagnostics.collected.take()
+        }
+
+        /// Runs `f`, routing what `libmagic` prints to stderr according to the diagnostics mode
+        fn diagnose<T>(&self, f: impl FnOnce() -> T) -> T {
+            match self.diagnostics.mode.get() {
+                DiagnosticsMode::Inherit => f(),
+                #[cfg(unix)]
+                mode => {
+                    let (res, output) = crate::magic::capture::capture(libc::STDERR_FILENO, f);
+                    self.diagnostics.dispatch(mode, &output);
+                    res
+                }
+                #[cfg(not(unix))]
+                _ => f(),
+            }
+        }
+
+        /// Like [`diagnose()`](Cookie::diagnose), but always captures stderr and returns what was captured
+        #[cfg(unix)]
+        fn diagnose_captured<T>(&self, f: impl FnOnce() -> T) -> (T, Vec<u8>) {
+            let (res, output) = crate::magic::capture::capture(libc::STDERR_FILENO, f);
+            self.diagnostics
+                .dispatch(self.diagnostics.mode.get(), &output);
+            (res, output)
+        }
+
+        pub fn set_flags(&self, flags: Flags) -> Result<(), SetFlagsError> {
+            let ret = crate::magic::setflags(&self.cookie, flags.bits() as _);
+            match ret {
+                Err(err) => Err(SetFlagsError {
+                    flags: self.rejected_flags(flags),
                     source: err,
                 }),
                 Ok(_) => Ok(()),
             }
         }
+
+        /// Returns the flags currently in effect for this cookie
+        #[doc(alias = "magic_getflags")]
+        pub fn flags(&self) -> Flags {
+            Flags::from_bits_retain(crate::magic::getflags(&self.cookie) as _)
+        }
+
+        /// Narrows down which of `flags` `magic_setflags()` refuses by trying them one at a time
+        ///
+        /// A failed `magic_setflags()` leaves the cookie flags untouched, successful attempts are undone afterwards.
+        fn rejected_flags(&self, flags: Flags) -> Flags {
+            let previous = crate::magic::getflags(&self.cookie);
+            let rejected = flags
+                .iter()
+                .filter(|flag| crate::magic::setflags(&self.cookie, flag.bits() as _).is_err())
+                .fold(Flags::empty(), |rejected, flag| rejected | flag);
+            let _ = crate::magic::setflags(&self.cookie, previous);
+
+            match rejected.is_empty() {
+                true => flags,
+                _ => rejected,
+            }
+        }
+
+        /// Applies all resource limits to this cookie
+        ///
+        /// Values too large for `libmagic` are rejected before any limit is applied.
+        /// Otherwise limits are applied one by one, so on error the ones preceding the failing parameter are already in effect.
+        #[doc(alias = "magic_setparam")]
+        pub fn set_limits(&self, limits: &Limits) -> Result<(), SetLimitsError> {
+            let params: Vec<_> = limits
+                .params()
+                .filter_map(|(name, param, value, max)| Some((name, param, value?, max)))
+                .collect();
+            if let Some(&(name, _, value, max)) =
+                params.iter().find(|(_, _, value, max)| value > max)
+            {
+                return Err(SetLimitsError {
+                    param: name,
+                    value,
+                    source: LimitsErrorSource::OutOfRange { max },
+                });
+            }
+
+            for (name, param, value, _) in params {
+                if let Err(err) = crate::magic::setparam(&self.cookie, param as _, value) {
+                    return Err(SetLimitsError {
+                        param: name,
+                        value,
+                        source: err.into(),
+                    });
+                }
+            }
+            Ok(())
+        }
+
+        /// Returns the resource limits currently in effect for this cookie
+        #[doc(alias = "magic_getparam")]
+        pub fn limits(&self) -> Result<Limits, LimitsError> {
+            let mut limits = Limits {
+                elf_shsize_max: None,
+                ..Limits::default()
+            };
+            for (name, param, _, _) in limits.params() {
+                match crate::magic::getparam(&self.cookie, param as _) {
+                    Ok(value) => limits.set_param(param, value),
+                    Err(err) => {
+                        return Err(LimitsError {
+                            param: name,
+                            source: err,
+                        })
+                    }
+                }
+            }
+            Ok(limits)
+        }
+
+        /// Compiles each magic source file in `filenames` into `<name>.mgc` in the current working directory
+        ///
+        /// See [`Cookie::compile_to()`](Cookie::compile_to) and [`Cookie::compile_to_vec()`](Cookie::compile_to_vec) to choose the destination.
+        pub fn compile(&self, filenames: &DatabasePaths) -> Result<(), Error> {
+            match self
+                .diagnose(|| crate::magic::compile(&self.cookie, filenames.filenames.as_deref()))
+            {
+                Err(err) => Err(Error {
+                    function: "magic_compile",
+                    source: err.into(),
+                }),
+                Ok(_) => Ok(()),
+            }
+        }
+
+        /// Compiles the magic source file or directory in `sources` into the database file `output`
+        ///
+        /// Unlike [`Cookie::compile()`](Cookie::compile), nothing is written into the working directory.
+        /// `sources` must name exactly one file or directory, a directory is compiled into a single database.
+        /// The database is compiled next to `output` and then renamed, so `output` is replaced atomically.
+        ///
+        /// # Working directory
+        ///
+        /// `libmagic` always writes into the working directory.
+        /// On Linux the compilation runs on a helper thread with a working directory of its own.
+        /// **Elsewhere the working directory of the whole process is switched for the duration of the call**,
+        /// so other threads resolving relative paths meanwhile resolve them against the staging directory.
+        /// Calls of this crate are serialized, but nothing else in the process is.
+        pub fn compile_to(&self, sources: &DatabasePaths, output: &Path) -> Result<(), Error> {
+            let dir = match output.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            self.compile_staged(sources, dir, "rename", |compiled| {
+                std::fs::rename(compiled, output)
+            })
+        }
+
+        /// Compiles the magic source file or directory in `sources` into memory
+        ///
+        /// The result can be passed to [`Cookie::load_buffers()`](Cookie::load_buffers).
+        /// The database is staged in [`std::env::temp_dir()`], see [`Cookie::compile_to()`](Cookie::compile_to).
+        /// Outside Linux this switches the working directory of the whole process for the duration of the call,
+        /// see [its section on the working directory](Cookie::compile_to#working-directory).
+        pub fn compile_to_vec(&self, sources: &DatabasePaths) -> Result<Vec<u8>, Error> {
+            self.compile_staged(sources, &std::env::temp_dir(), "read", |compiled| {
+                std::fs::read(compiled)
+            })
+        }
+
+        /// Compiles `sources` into a fresh directory within `dir` and hands the database file to `finish`
+        ///
+        /// I/O errors are reported with the name of the failed operation, `finishing` for those of `finish`.
+        fn compile_staged<T>(
+            &self,
+            sources: &DatabasePaths,
+            dir: &Path,
+            finishing: &'static str,
+            finish: impl FnOnce(&Path) -> std::io::Result<T>,
+        ) -> Result<T, Error> {
+            let io_error = |function| {
+                move |err: std::io::Error| Error {
+                    function,
+                    source: ErrorSource::Io(err),
+                }
+            };
+
+            let source = sources
+                .compile_source()
+                .map_err(io_error("resolve_sources"))?;
+            let staging = StagingDir::new(dir).map_err(io_error("create_dir"))?;
+            let res = self
+                .diagnose(|| crate::magic::compile_in(&self.cookie, Some(&source), staging.path()));
+            match res.map_err(io_error("set_current_dir"))? {
+                Err(err) => Err(Error {
+                    function: "magic_compile",
+                    source: err.into(),
+                }),
+                Ok(_) => {
+                    // `libmagic` names the database after the source, which is the only file in there
+                    let compiled = std::fs::read_dir(staging.path())
+                        .and_then(|mut entries| {
+                            entries.next().unwrap_or_else(|| {
+                                Err(std::io::Error::new(
+                                    std::io::ErrorKind::NotFound,
+                                    "`magic_compile()` did not write a database",
+                                ))
+                            })
+                        })
+                        .map_err(io_error("read_dir"))?;
+                    finish(&compiled.path()).map_err(io_error(finishing))
+                }
+            }
+        }
+
+        /// Checks the magic source files in `filenames` for validity
+        ///
+        /// `libmagic` prints warnings about individual entries to stderr, which is captured for the duration of the call
+        /// and also routed according to the [`DiagnosticsMode`].
+        /// Capturing is only available on unix, elsewhere the warnings still go to stderr and are not returned.
+        pub fn check(&self, filenames: &DatabasePaths) -> Result<Vec<Diagnostic>, CheckError> {
+            let check = || crate::magic::check(&self.cookie, filenames.filenames.as_deref());
+            #[cfg(unix)]
+            let (res, output) = self.diagnose_captured(check);
+            #[cfg(not(unix))]
+            let (res, output) = (self.diagnose(check), Vec::new());
+
+            let mut diagnostics = Diagnostic::parse_warnings(&String::from_utf8_lossy(&output));
+            match res {
+                Err(err) => {
+                    diagnostics.push(Diagnostic {
+                        path: None,
+                        line: None,
+                        severity: Severity::Error,
+                        message: err.explanation.to_string_lossy().into_owned(),
+                    });
+                    Err(CheckError {
+                        diagnostics,
+                        source: Error {
+                            function: "magic_check",
+                            source: err.into(),
+                        },
+                    })
+                }
+                Ok(_) => Ok(diagnostics),
+            }
+        }
+
+        /// Lists the entries of the database files in `filenames`, strongest first within each set
+        ///
+        /// `libmagic` prints the listing to stdout, which is captured for the duration of the call.
+        /// The process-wide stdout is redirected, so anything other threads write to stdout meanwhile
+        /// is swallowed and never reaches the terminal.
+        /// Capturing is only available on unix, elsewhere this fails with [`ErrorKind::Unsupported`].
+        pub fn list(&self, filenames: &DatabasePaths) -> Result<Vec<MagicEntry>, Error> {
+            #[cfg(unix)]
+            {
+                let list = || {
+                    self.diagnose(|| {
+                        crate::magic::list(&self.cookie, filenames.filenames.as_deref())
+                    })
+                };
+                let (res, output) = crate::magic::capture::capture(libc::STDOUT_FILENO, list);
+
+                match res {
+                    Err(err) => Err(Error {
+                        function: "magic_list",
+                        source: err.into(),
+                    }),
+                    Ok(_) => Ok(MagicEntry::parse_list(&String::from_utf8_lossy(&output))),
+                }
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = filenames;
+                Err(Error {
+                    function: "magic_list",
+                    source: ErrorSource::Io(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "the listing can only be captured on unix",
+                    )),
+                })
+            }
+        }
     }
 
     /// Error within [`Cookie::open()`](Cookie::open)
@@ -366,6 +2037,30 @@ pub mod cookie {
         source: crate::magic::OpenError,
     }
 
+    impl OpenError {
+        /// Flags the cookie was to be opened with
+        pub fn flags(&self) -> Flags {
+            self.flags
+        }
+
+        /// Classification of this error
+        pub fn kind(&self) -> ErrorKind {
+            match self.kind {
+                OpenErrorKind::UnsupportedFlags => ErrorKind::UnsupportedFlags,
+                OpenErrorKind::Errno => match self.errno().kind() {
+                    std::io::ErrorKind::NotFound => ErrorKind::FileNotFound,
+                    std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                    _ => ErrorKind::Io,
+                },
+            }
+        }
+
+        /// OS error that made `magic_open()` fail
+        pub fn errno(&self) -> &std::io::Error {
+            self.source.errno()
+        }
+    }
+
     /// Kind of [`OpenError`]
     #[derive(Debug)]
     enum OpenErrorKind {
@@ -385,6 +2080,550 @@ pub mod cookie {
         //#[backtrace]
         source: crate::magic::SetFlagsError,
     }
+
+    impl SetFlagsError {
+        /// Flags that were rejected
+        pub fn flags(&self) -> Flags {
+            self.flags
+        }
+
+        /// Classification of this error, always [`ErrorKind::UnsupportedFlags`]
+        pub fn kind(&self) -> ErrorKind {
+            ErrorKind::UnsupportedFlags
+        }
+    }
+
+    /// Error within [`Cookie::check()`](Cookie::check)
+    ///
+    /// Carries the diagnostics collected up to the failure, the last one being the error itself.
+    #[derive(thiserror::Error, Debug)]
+    #[error("magic source files failed the check with {} diagnostics", .diagnostics.len())]
+    pub struct CheckError {
+        diagnostics: Vec<Diagnostic>,
+        //#[backtrace]
+        source: Error,
+    }
+
+    impl CheckError {
+        /// Diagnostics about the checked files
+        pub fn diagnostics(&self) -> &[Diagnostic] {
+            &self.diagnostics
+        }
+
+        /// Underlying `libmagic` error
+        pub fn error(&self) -> &Error {
+            &self.source
+        }
+
+        /// Classification of this error, see [`Error::kind()`](Error::kind)
+        pub fn kind(&self) -> ErrorKind {
+            self.source.kind()
+        }
+    }
+
+    /// Error within [`Cookie::set_limits()`](Cookie::set_limits)
+    #[derive(thiserror::Error, Debug)]
+    #[error("could not set magic cookie limit `{}` to {}", .param, .value)]
+    pub struct SetLimitsError {
+        param: &'static str,
+        value: usize,
+        //#[backtrace]
+        source: LimitsErrorSource,
+    }
+
+    impl SetLimitsError {
+        /// Name of the `MAGIC_PARAM_*` limit that could not be set
+        pub fn param(&self) -> &'static str {
+            self.param
+        }
+
+        /// Value the limit was to be set to
+        pub fn value(&self) -> usize {
+            self.value
+        }
+
+        /// Largest value `libmagic` can store for this limit, if `value` exceeded it
+        pub fn max(&self) -> Option<usize> {
+            match self.source {
+                LimitsErrorSource::OutOfRange { max } => Some(max),
+                LimitsErrorSource::Param(_) => None,
+            }
+        }
+
+        /// Classification of this error, [`ErrorKind::LimitExceeded`] if `value` is too large for `libmagic`
+        pub fn kind(&self) -> ErrorKind {
+            match &self.source {
+                LimitsErrorSource::OutOfRange { .. } => ErrorKind::LimitExceeded,
+                LimitsErrorSource::Param(err) => err.kind(),
+            }
+        }
+
+        /// OS error `magic_setparam()` failed with, if it got that far
+        pub fn errno(&self) -> Option<&std::io::Error> {
+            match &self.source {
+                LimitsErrorSource::OutOfRange { .. } => None,
+                LimitsErrorSource::Param(err) => Some(err.errno()),
+            }
+        }
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    enum LimitsErrorSource {
+        #[error("{}", .0)]
+        Param(#[from] crate::magic::ParamError),
+        #[error("`libmagic` cannot store values above {}", .max)]
+        OutOfRange { max: usize },
+    }
+
+    /// Error within [`Cookie::limits()`](Cookie::limits)
+    #[derive(thiserror::Error, Debug)]
+    #[error("could not get magic cookie limit `{}`", .param)]
+    pub struct LimitsError {
+        param: &'static str,
+        //#[backtrace]
+        source: crate::magic::ParamError,
+    }
+
+    impl LimitsError {
+        /// Name of the `MAGIC_PARAM_*` limit that could not be read
+        pub fn param(&self) -> &'static str {
+            self.param
+        }
+
+        /// Classification of this error
+        pub fn kind(&self) -> ErrorKind {
+            self.source.kind()
+        }
+
+        /// OS error `magic_getparam()` failed with
+        pub fn errno(&self) -> &std::io::Error {
+            self.source.errno()
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) mod tests {
+        use super::*;
+
+        /// Magic source file in [`std::env::temp_dir()`], removed when dropped
+        pub(crate) struct MagicSource(PathBuf);
+
+        impl MagicSource {
+            pub(crate) fn new(contents: &str) -> Self {
+                static COUNTER: std::sync::atomic::AtomicUsize =
+                    std::sync::atomic::AtomicUsize::new(0);
+
+                let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let path = std::env::temp_dir().join(format!(
+                    "libmagic-rs-test-{}-{}.magic",
+                    std::process::id(),
+                    n
+                ));
+                std::fs::write(&path, contents).unwrap();
+                Self(path)
+            }
+
+            pub(crate) fn path(&self) -> &Path {
+                &self.0
+            }
+        }
+
+        impl Drop for MagicSource {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_file(&self.0);
+            }
+        }
+
+        /// Database compiled from the magic source `contents`
+        pub(crate) fn compiled(contents: &str) -> Vec<u8> {
+            let source = MagicSource::new(contents);
+            Cookie::open(Flags::empty())
+                .unwrap()
+                .compile_to_vec(&DatabasePaths::new([source.path()]).unwrap())
+                .unwrap()
+        }
+
+        #[test]
+        #[cfg(feature = "zlib")]
+        fn zlib_build_decompresses_gzip() {
+            // "hello\n", only recognized as text once decompressed
+            const TEXT_GZIP: &[u8] = &[
+                0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+                0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+            ];
+
+            assert!(Compression::supported().contains(Compression::GZIP));
+
+            // `libmagic` refuses to identify anything without a database, although text detection is built in
+            let cookie = Cookie::open(Flags::COMPRESS)
+                .unwrap()
+                .load_buffers([compiled("0\tstring\tNOTHING\tnothing\n")])
+                .unwrap();
+
+            assert!(cookie.buffer(TEXT_GZIP).unwrap().starts_with("ASCII text"));
+            cookie
+                .set_flags(Flags::COMPRESS | Flags::NO_COMPRESS_FORK)
+                .unwrap();
+            assert!(!cookie.buffer(TEXT_GZIP).unwrap().starts_with("ASCII text"));
+        }
+
+        #[test]
+        fn warnings_with_path_and_line_are_parsed() {
+            let diagnostics =
+                Diagnostic::parse_warnings("/tmp/magic/local, 12: Warning: type `strin' invalid\n");
+
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].path(), Some(Path::new("/tmp/magic/local")));
+            assert_eq!(diagnostics[0].line(), Some(12));
+            assert_eq!(diagnostics[0].severity(), Severity::Warning);
+            assert_eq!(diagnostics[0].message(), "type `strin' invalid");
+        }
+
+        #[test]
+        fn warnings_without_line_are_parsed() {
+            let diagnostics = Diagnostic::parse_warnings(
+                "Warning: using regular magic file `/tmp/magic/local'\nunrelated output\n",
+            );
+
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].path(), None);
+            assert_eq!(diagnostics[0].line(), None);
+            assert_eq!(
+                diagnostics[0].message(),
+                "using regular magic file `/tmp/magic/local'"
+            );
+        }
+
+        #[test]
+        #[cfg(unix)]
+        fn failed_check_ends_with_the_error() {
+            let source = MagicSource::new("0\tstrin\tX\tbroken\n");
+
+            let cookie = Cookie::open(Flags::default()).unwrap();
+            cookie.set_diagnostics(DiagnosticsMode::Collect);
+            let err = cookie
+                .check(&[source.path()].try_into().unwrap())
+                .unwrap_err();
+
+            let diagnostics = err.diagnostics();
+            let last = diagnostics.last().unwrap();
+            assert_eq!(last.severity(), Severity::Error);
+            assert_eq!(last.path(), None);
+            assert!(!last.message().is_empty());
+            assert!(diagnostics[..diagnostics.len() - 1]
+                .iter()
+                .all(|diagnostic| diagnostic.severity() == Severity::Warning));
+            assert!(!cookie.take_diagnostics().is_empty());
+        }
+
+        /// `magic_list()` output of `libmagic` 5.44 for a small magic source file
+        const LISTING: &str = "\
+Set 0:
+Binary patterns:
+Strength =  80@3: PDF document [application/pdf]
+Strength =  71@9:  []
+Strength =  70@1: Zip archive data [application/zip]
+Text patterns:
+Strength =  80@8: greeting - with dashes [bracketed] []
+Strength =  39@6: POSIX shell script text executable [text/x-shellscript]
+Set 1:
+Binary patterns:
+Text patterns:
+";
+
+        #[test]
+        fn listing_is_parsed() {
+            let entries = MagicEntry::parse_list(LISTING);
+
+            assert_eq!(entries.len(), 5);
+            assert!(entries.iter().all(|entry| entry.set() == 0));
+            assert_eq!(entries[0].kind(), PatternKind::Binary);
+            assert_eq!(entries[0].strength(), 80);
+            assert_eq!(entries[0].line(), 3);
+            assert_eq!(entries[0].description(), "PDF document");
+            assert_eq!(entries[0].mime_type(), Some("application/pdf"));
+            assert_eq!(entries[4].kind(), PatternKind::Text);
+            assert_eq!(entries[4].strength(), 39);
+            assert_eq!(entries[4].mime_type(), Some("text/x-shellscript"));
+        }
+
+        #[test]
+        fn listing_keeps_empty_and_bracketed_descriptions() {
+            let entries = MagicEntry::parse_list(LISTING);
+
+            assert_eq!(entries[1].line(), 9);
+            assert_eq!(entries[1].description(), "");
+            assert_eq!(entries[1].mime_type(), None);
+            assert_eq!(entries[3].kind(), PatternKind::Text);
+            assert_eq!(
+                entries[3].description(),
+                "greeting - with dashes [bracketed]"
+            );
+            assert_eq!(entries[3].mime_type(), None);
+        }
+
+        #[test]
+        fn listing_skips_unknown_lines() {
+            let entries =
+                MagicEntry::parse_list("Set 2:\nText patterns:\nStrength = x@1: y []\nnoise\n");
+            assert!(entries.is_empty());
+
+            let entries =
+                MagicEntry::parse_list("Set 2:\nText patterns:\nStrength = 1@1: y [text/y]\n");
+            assert_eq!(entries[0].set(), 2);
+            assert_eq!(entries[0].kind(), PatternKind::Text);
+        }
+
+        #[test]
+        fn matches_are_split_on_the_separator() {
+            let matches = Match::split(
+                "ELF 64-bit LSB pie executable\n- data",
+                "application/x-pie-executable\n- application/octet-stream",
+            );
+
+            assert_eq!(matches.len(), 2);
+            assert_eq!(matches[0].description(), "ELF 64-bit LSB pie executable");
+            assert_eq!(matches[0].mime_type(), Some("application/x-pie-executable"));
+            assert_eq!(matches[1].description(), "data");
+            assert_eq!(matches[1].mime_type(), Some("application/octet-stream"));
+        }
+
+        #[test]
+        fn matches_keep_dashes_within_descriptions() {
+            let matches = Match::split(
+                "Microsoft Excel 2007+ - spreadsheet\n- Zip archive data, at least v2.0 - deflate",
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet\n- application/zip",
+            );
+
+            assert_eq!(matches.len(), 2);
+            assert_eq!(
+                matches[0].description(),
+                "Microsoft Excel 2007+ - spreadsheet"
+            );
+            assert_eq!(
+                matches[1].description(),
+                "Zip archive data, at least v2.0 - deflate"
+            );
+            assert_eq!(matches[1].mime_type(), Some("application/zip"));
+
+            let matches = Match::split("- leading dash\n-trailing\n", "text/plain");
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].description(), "- leading dash\n-trailing\n");
+        }
+
+        #[test]
+        fn mime_types_are_only_attributed_if_aligned() {
+            let matches = Match::split("first\n- second\n- third", "text/x-first\n- text/x-third");
+
+            assert_eq!(matches.len(), 3);
+            assert!(matches.iter().all(|m| m.mime_type().is_none()));
+
+            let matches = Match::split(
+                "first\n- second\n- third",
+                "text/x-first\n- \n- text/x-third",
+            );
+            assert_eq!(matches[0].mime_type(), Some("text/x-first"));
+            assert_eq!(matches[1].mime_type(), None);
+            assert_eq!(matches[2].mime_type(), Some("text/x-third"));
+
+            let matches = Match::split("only", "");
+            assert_eq!(matches[0].mime_type(), None);
+        }
+
+        #[test]
+        fn errors_are_classified_by_errno_first() {
+            let errno = std::io::Error::from_raw_os_error(libc::ENOENT);
+            let explanation = "cannot stat `x' (No such file or directory)";
+
+            assert_eq!(
+                ErrorKind::from_error("magic_file", Some(&errno), explanation),
+                ErrorKind::FileNotFound
+            );
+            assert_eq!(
+                ErrorKind::from_error("magic_load", Some(&errno), explanation),
+                ErrorKind::DatabaseMissing
+            );
+        }
+
+        #[test]
+        fn errors_are_classified_by_known_messages() {
+            let kind = |function, explanation| ErrorKind::from_error(function, None, explanation);
+
+            assert_eq!(
+                kind(
+                    "magic_load",
+                    "File 5.44 supports only version 18 magic files. `x' is version 14"
+                ),
+                ErrorKind::DatabaseVersionMismatch
+            );
+            assert_eq!(
+                kind("magic_load", "could not find any valid magic files!"),
+                ErrorKind::DatabaseMissing
+            );
+            assert_eq!(
+                kind("magic_check", "could not find any valid magic files!"),
+                ErrorKind::InvalidMagicSyntax
+            );
+            assert_eq!(
+                kind("magic_compile", "line 3: type `strin' invalid"),
+                ErrorKind::InvalidMagicSyntax
+            );
+            assert_eq!(
+                kind("magic_buffer", "indirect count (16) exceeded"),
+                ErrorKind::LimitExceeded
+            );
+            assert_eq!(
+                kind("magic_buffer", "name use count (50) exceeded"),
+                ErrorKind::LimitExceeded
+            );
+        }
+
+        #[test]
+        fn unknown_messages_are_not_guessed_at() {
+            let kind = |function, explanation| ErrorKind::from_error(function, None, explanation);
+
+            assert_eq!(
+                kind("magic_load", "error reading magic"),
+                ErrorKind::Unknown
+            );
+            assert_eq!(kind("magic_file", "line 3: bad magic"), ErrorKind::Unknown);
+            assert_eq!(kind("magic_buffer", "too many errors"), ErrorKind::Unknown);
+        }
+
+        #[test]
+        fn supported_flags_contain_those_in_the_bindings() {
+            let bindings = include_str!(concat!(env!("OUT_DIR"), "/magic_sys.rs"));
+            let defined: Vec<&str> = bindings
+                .lines()
+                .filter_map(|line| line.strip_prefix("pub const MAGIC_"))
+                .filter_map(|line| line.split(':').next())
+                .collect();
+
+            let supported = Flags::supported();
+            for (name, flag) in Flags::all().iter_names() {
+                if defined.contains(&name) {
+                    assert!(
+                        supported.contains(flag),
+                        "{} is defined but not supported",
+                        name
+                    );
+                }
+            }
+            assert!(defined.contains(&"NO_CHECK_CSV"));
+        }
+
+        fn identification(mime: &str) -> Identification {
+            Identification::new(String::new(), mime, "???".to_string(), String::new())
+        }
+
+        #[test]
+        fn identification_splits_mime_type_and_charset() {
+            let identification = identification("text/plain; charset=us-ascii");
+            assert_eq!(identification.mime_type(), "text/plain");
+            assert_eq!(identification.encoding(), "us-ascii");
+        }
+
+        #[test]
+        fn identification_without_charset_has_empty_encoding() {
+            let identification = identification("text/plain");
+            assert_eq!(identification.mime_type(), "text/plain");
+            assert_eq!(identification.encoding(), "");
+        }
+
+        #[test]
+        fn identification_of_empty_input() {
+            let identification = identification("application/x-empty; charset=binary");
+            assert_eq!(identification.mime_type(), "application/x-empty");
+            assert_eq!(identification.encoding(), "binary");
+            assert!(identification.extensions().is_empty());
+        }
+
+        #[test]
+        fn misaligned_static_buffers_are_copied() {
+            #[repr(C, align(8))]
+            struct Aligned<T: ?Sized>(T);
+            static BYTES: &Aligned<[u8]> = &Aligned([0; 16]);
+
+            let aligned = DatabaseBuffer::from(&BYTES.0);
+            assert!(matches!(aligned.0, DatabaseBufferInner::Static(_)));
+
+            let misaligned = DatabaseBuffer::from(&BYTES.0[1..]);
+            assert!(matches!(misaligned.0, DatabaseBufferInner::Shared(_)));
+            assert_eq!(misaligned.as_bytes().as_ptr().align_offset(8), 0);
+            assert_eq!(misaligned.as_bytes(), &BYTES.0[1..]);
+        }
+
+        #[test]
+        fn identification_splits_extensions() {
+            let identification = Identification::new(
+                String::new(),
+                "text/x-pascal",
+                "pr/prb".to_string(),
+                String::new(),
+            );
+            assert_eq!(identification.extensions(), ["pr", "prb"]);
+        }
+
+        #[test]
+        fn identification_splits_apple_creator_and_type() {
+            let apple = |apple: &str| {
+                Identification::new(String::new(), "", "???".to_string(), apple.to_string())
+            };
+
+            let identification = apple("CAROPDF ");
+            assert_eq!(identification.apple_creator(), Some("CARO"));
+            assert_eq!(identification.apple_type(), Some("PDF "));
+
+            let identification = apple("UNKNTEXT");
+            assert_eq!(identification.apple_creator(), None);
+            assert_eq!(identification.apple_type(), Some("TEXT"));
+
+            let identification = apple("UNKNUNKN");
+            assert_eq!(identification.apple_creator(), None);
+            assert_eq!(identification.apple_type(), None);
+        }
+
+        #[test]
+        fn hardened_limits_round_trip() {
+            let cookie = Cookie::open(Flags::empty()).unwrap();
+            cookie.set_limits(&Limits::hardened()).unwrap();
+
+            let mut expected = Limits::hardened();
+            if crate::magic::version() < 545 {
+                expected.elf_shsize_max = None;
+            }
+            assert_eq!(cookie.limits().unwrap(), expected);
+        }
+
+        #[test]
+        fn limits_too_large_for_libmagic_are_rejected() {
+            let cookie = Cookie::open(Flags::empty()).unwrap();
+            let before = cookie.limits().unwrap();
+
+            let err = cookie
+                .set_limits(Limits::hardened().set_indir_max(100_000))
+                .unwrap_err();
+            assert_eq!(err.param(), "MAGIC_PARAM_INDIR_MAX");
+            assert_eq!(err.value(), 100_000);
+            assert_eq!(err.max(), Some(u16::MAX as usize));
+            // nothing was applied
+            assert_eq!(cookie.limits().unwrap(), before);
+        }
+
+        #[test]
+        fn limits_unknown_to_libmagic_read_back_as_none() {
+            let cookie = Cookie::open(Flags::empty()).unwrap();
+            let limits = cookie.limits().unwrap();
+            assert_eq!(
+                limits.elf_shsize_max().is_some(),
+                crate::magic::version() >= 545
+            );
+        }
+    }
 } // mod cookie
 
+#[cfg(feature = "tokio")]
+pub use crate::async_magic::AsyncMagic;
 pub use crate::cookie::Cookie;
+pub use crate::pool::CookiePool;
+pub use crate::reload::ReloadableMagic;
@@ -0,0 +1,160 @@
+// redirection of what `libmagic` prints to stdout/stderr into a pipe
+
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// Redirecting a standard stream is process-wide, so only one thread may capture at a time
+static CAPTURE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+thread_local! {
+    /// Whether this thread holds `CAPTURE_LOCK`, so that captures of several fds can be nested
+    static CAPTURING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Runs `f` while everything written to `fd` goes into a buffer instead
+///
+/// Output of other threads to `fd` during that time ends up in the buffer as well.
+/// If the redirection cannot be set up, `f` runs with `fd` untouched and the buffer stays empty.
+/// `fd` is restored even if `f` panics.
+///
+/// Each call spawns a thread draining the pipe, so capturing costs a thread spawn on top of `f`.
+pub(crate) fn capture<T>(fd: libc::c_int, f: impl FnOnce() -> T) -> (T, Vec<u8>) {
+    let _lock = match CAPTURING.get() {
+        true => None,
+        _ => Some(CaptureLock::acquire()),
+    };
+
+    let (read_end, redirection) = match Redirection::new(fd) {
+        Ok(redirected) => redirected,
+        Err(err) => {
+            log::warn!(
+                "could not capture output of `libmagic` on fd {}: {}",
+                fd,
+                err
+            );
+            return (f(), Vec::new());
+        }
+    };
+
+    // drain concurrently, `libmagic` would block once the pipe buffer is full
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = std::fs::File::from(read_end).read_to_end(&mut output);
+        output
+    });
+
+    let res = f();
+    drop(redirection);
+
+    (res, reader.join().unwrap_or_default())
+}
+
+/// `fd` pointing to the write end of a pipe until dropped
+struct Redirection {
+    fd: libc::c_int,
+    saved: OwnedFd,
+}
+
+impl Redirection {
+    /// Points `fd` to a new pipe, returning its read end
+    fn new(fd: libc::c_int) -> std::io::Result<(OwnedFd, Self)> {
+        flush();
+
+        let (read_end, write_end) = pipe()?;
+
+        let saved = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if saved == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let saved = unsafe { OwnedFd::from_raw_fd(saved) };
+
+        if unsafe { libc::dup2(write_end.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok((read_end, Redirection { fd, saved }))
+    }
+}
+
+impl Drop for Redirection {
+    fn drop(&mut self) {
+        flush();
+        // replacing `fd` closes the last write end of the pipe, so the reader sees EOF
+        unsafe { libc::dup2(self.saved.as_raw_fd(), self.fd) };
+    }
+}
+
+/// Creates a pipe, returning its read and write end
+///
+/// Both ends are close-on-exec, so they do not leak into processes `libmagic` spawns meanwhile.
+pub(crate) fn pipe() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as libc::c_int; 2];
+    #[cfg(not(target_vendor = "apple"))]
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // no `pipe2()` there, the ends may leak into a process forked by another thread in between
+    #[cfg(target_vendor = "apple")]
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let fds = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    #[cfg(target_vendor = "apple")]
+    for fd in [&fds.0, &fds.1] {
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(fds)
+}
+
+/// Flushes what Rust and C buffered for the standard streams so it ends up where it was meant to go
+fn flush() {
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    unsafe { libc::fflush(std::ptr::null_mut()) };
+}
+
+struct CaptureLock(#[allow(dead_code)] std::sync::MutexGuard<'static, ()>);
+
+impl CaptureLock {
+    fn acquire() -> Self {
+        let guard = CAPTURE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        CAPTURING.set(true);
+        CaptureLock(guard)
+    }
+}
+
+impl Drop for CaptureLock {
+    fn drop(&mut self) {
+        CAPTURING.set(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fd_is_restored_after_a_panic() {
+        let (read_end, write_end) = pipe().unwrap();
+        let fd = write_end.as_raw_fd();
+
+        let res = std::panic::catch_unwind(|| {
+            capture(fd, || {
+                let _ = std::fs::File::from(write_end.try_clone().unwrap()).write_all(b"captured");
+                panic!("within capture");
+            })
+        });
+        assert!(res.is_err());
+
+        std::fs::File::from(write_end)
+            .write_all(b"restored")
+            .unwrap();
+        let mut output = Vec::new();
+        std::fs::File::from(read_end)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, b"restored");
+    }
+}
@@ -0,0 +1,118 @@
+// minimal inotify wrapper to notice changed database files
+
+use std::ffi::OsString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+
+pub(crate) use super::capture::pipe;
+
+/// Outcome of [`Inotify::wait()`]
+pub(crate) enum Wait {
+    /// Watch descriptor and name within a watched directory of each event
+    Events(Vec<(libc::c_int, Option<OsString>)>),
+    Timeout,
+    /// The write end of the stop pipe was closed
+    Stopped,
+}
+
+pub(crate) struct Inotify {
+    fd: OwnedFd,
+}
+
+impl Inotify {
+    pub fn new() -> std::io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Watches `path` for the events in `mask`, returning the watch descriptor
+    pub fn watch(&self, path: &std::path::Path, mask: u32) -> std::io::Result<libc::c_int> {
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) };
+        if wd == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(wd)
+    }
+
+    /// Waits for events until `timeout` passes or the write end of `stop` is closed
+    pub fn wait(
+        &self,
+        stop: &OwnedFd,
+        timeout: Option<std::time::Duration>,
+    ) -> std::io::Result<Wait> {
+        let mut fds = [
+            libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: stop.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int
+        });
+
+        match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) } {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                match err.kind() {
+                    std::io::ErrorKind::Interrupted => Ok(Wait::Events(Vec::new())),
+                    _ => Err(err),
+                }
+            }
+            0 => Ok(Wait::Timeout),
+            _ if fds[1].revents != 0 => Ok(Wait::Stopped),
+            _ => self.read().map(Wait::Events),
+        }
+    }
+
+    fn read(&self) -> std::io::Result<Vec<(libc::c_int, Option<OsString>)>> {
+        // aligned for `inotify_event`, large enough for at least one event with the longest name
+        let mut buffer = vec![0u64; 4096 / std::mem::size_of::<u64>()];
+        let len = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len() * std::mem::size_of::<u64>(),
+            )
+        };
+        if len == -1 {
+            let err = std::io::Error::last_os_error();
+            return match err.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(err),
+            };
+        }
+
+        let bytes =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), len as usize) };
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while offset + header <= bytes.len() {
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(bytes[offset..].as_ptr().cast()) };
+            let name = &bytes[offset + header..][..event.len as usize];
+            // the name is padded with NUL bytes
+            let name = name.split(|b| *b == 0).next().unwrap_or_default();
+            let name = match name.is_empty() {
+                true => None,
+                _ => Some(std::ffi::OsStr::from_bytes(name).to_owned()),
+            };
+            events.push((event.wd, name));
+            offset += header + event.len as usize;
+        }
+        Ok(events)
+    }
+}
@@ -1,9 +1,17 @@
 #![allow(unsafe_code)]
 // from :: https://github.com/robo9k/rust-magic/blob/main/src/ffi.rs
 
-pub mod magic_sys;
+/// Bindings to the `magic.h` of the linked `libmagic`, selected or generated by `build.rs`
+pub mod magic_sys {
+    include!(concat!(env!("OUT_DIR"), "/magic_sys.rs"));
+}
 pub use magic_sys::*;
 
+#[cfg(unix)]
+pub(crate) mod capture;
+#[cfg(target_os = "linux")]
+pub(crate) mod inotify;
+
 pub const MAGIC_MIME: u32 = MAGIC_MIME_TYPE | MAGIC_MIME_ENCODING;
 pub const MAGIC_NODESC: u32 = MAGIC_EXTENSION | MAGIC_MIME | MAGIC_APPLE;
 pub const MAGIC_NO_CHECK_BUILTIN: u32 = MAGIC_NO_CHECK_COMPRESS |
@@ -18,6 +26,17 @@ pub const MAGIC_NO_CHECK_BUILTIN: u32 = MAGIC_NO_CHECK_COMPRESS |
     MAGIC_NO_CHECK_ENCODING |
     MAGIC_NO_CHECK_JSON;
 
+// NOTE: introduced after 5.23, defined here as well so that bindings of older versions still build,
+// whether the linked `libmagic` supports them is checked at runtime
+pub const MAGIC_NO_CHECK_CSV: u32 = 0x0080000;
+pub const MAGIC_NO_CHECK_JSON: u32 = 0x0400000;
+pub const MAGIC_NO_CHECK_SIMH: u32 = 0x0800000;
+pub const MAGIC_NO_COMPRESS_FORK: u32 = 0x4000000;
+pub const MAGIC_PARAM_REGEX_MAX: u32 = 5;
+pub const MAGIC_PARAM_BYTES_MAX: u32 = 6;
+pub const MAGIC_PARAM_ENCODING_MAX: u32 = 7;
+pub const MAGIC_PARAM_ELF_SHSIZE_MAX: u32 = 8;
+
 // NOTE: the following are from `file.h`, but part of `magic.h` API
 pub const FILE_LOAD: u32 = 0;
 pub const FILE_CHECK: u32 = 1;
@@ -35,6 +54,10 @@ impl Cookie {
     }
 }
 
+// `libmagic` keeps no thread-local state for a `magic_t`, so moving one to another thread is fine.
+// It is not `Sync` though: concurrent calls on the same `magic_t` race on its internal buffers.
+unsafe impl Send for Cookie {}
+
 /// Error for opened `magic_t` instance
 #[derive(thiserror::Error, Debug)]
 #[error("magic cookie error ({}): {}",
@@ -47,6 +70,18 @@ match .errno {
 pub(crate) struct CookieError {
     pub explanation: std::ffi::CString,
     errno: Option<std::io::Error>,
+    /// `explanation` describes how `magic_sys` violated its API contract instead of being set by it
+    api_violation: bool,
+}
+
+impl CookieError {
+    pub fn errno(&self) -> Option<&std::io::Error> {
+        self.errno.as_ref()
+    }
+
+    pub fn is_api_violation(&self) -> bool {
+        self.api_violation
+    }
 }
 
 fn last_error(cookie: &Cookie) -> Option<CookieError> {
@@ -63,15 +98,38 @@ fn last_error(cookie: &Cookie) -> Option<CookieError> {
                 0 => None,
                 _ => Some(std::io::Error::from_raw_os_error(errno)),
             },
+            api_violation: false,
         })
     }
 }
 
-fn api_violation(cookie: &Cookie, description: String) -> ! {
-    panic!(
+static STRICT_API: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub(crate) fn set_strict_api(strict: bool) {
+    STRICT_API.store(strict, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// # Panics
+///
+/// Panics in strict mode, see [`set_strict_api()`].
+fn api_violation(cookie: &Cookie, description: String) -> CookieError {
+    if STRICT_API.load(std::sync::atomic::Ordering::Relaxed) {
+        panic!(
+            "`magic_sys` API violation for magic cookie {:?}: {}",
+            cookie, description
+        );
+    }
+    log::error!(
         "`magic_sys` API violation for magic cookie {:?}: {}",
-        cookie, description
+        cookie,
+        description
     );
+
+    CookieError {
+        explanation: std::ffi::CString::new(description).unwrap_or_default(),
+        errno: None,
+        api_violation: true,
+    }
 }
 
 fn expect_error(cookie: &Cookie, description: String) -> CookieError {
@@ -85,9 +143,7 @@ pub(crate) fn close(cookie: &mut Cookie) {
     unsafe { magic_sys::magic_close(cookie.0) }
 }
 
-/// # Panics
-///
-/// Panics if `magic_sys` violates its API contract, e.g. by not setting the last error.
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error, are reported as a [`CookieError`].
 pub(crate) fn file(
     cookie: &Cookie,
     filename: &std::ffi::CStr, // TODO: Support NULL
@@ -106,9 +162,7 @@ pub(crate) fn file(
     }
 }
 
-/// # Panics
-///
-/// Panics if `magic_sys` violates its API contract, e.g. by not setting the last error.
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error, are reported as a [`CookieError`].
 pub(crate) fn buffer(cookie: &Cookie, buffer: &[u8]) -> Result<std::ffi::CString, CookieError> {
     let buffer_ptr = buffer.as_ptr();
     let buffer_len = buffer.len() as libc::size_t;
@@ -125,6 +179,24 @@ pub(crate) fn buffer(cookie: &Cookie, buffer: &[u8]) -> Result<std::ffi::CString
     }
 }
 
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error, are reported as a [`CookieError`].
+pub(crate) fn descriptor(
+    cookie: &Cookie,
+    fd: libc::c_int,
+) -> Result<std::ffi::CString, CookieError> {
+    let res = unsafe { magic_sys::magic_descriptor(cookie.0, fd) };
+
+    if res.is_null() {
+        Err(expect_error(
+            cookie,
+            "`magic_descriptor()` did not set last error".to_string(),
+        ))
+    } else {
+        let c_str = unsafe { std::ffi::CStr::from_ptr(res) };
+        Ok(c_str.into())
+    }
+}
+
 pub(crate) fn setflags(cookie: &Cookie, flags: libc::c_int) -> Result<(), SetFlagsError> {
     let ret = unsafe { magic_sys::magic_setflags(cookie.0, flags) };
     match ret {
@@ -133,15 +205,67 @@ pub(crate) fn setflags(cookie: &Cookie, flags: libc::c_int) -> Result<(), SetFla
     }
 }
 
+pub(crate) fn getflags(cookie: &Cookie) -> libc::c_int {
+    unsafe { magic_sys::magic_getflags(cookie.0) }
+}
+
 #[derive(thiserror::Error, Debug)]
 #[error("could not set magic cookie flags {}", .flags)]
 pub(crate) struct SetFlagsError {
     flags: libc::c_int,
 }
 
-/// # Panics
-///
-/// Panics if `magic_sys` violates its API contract, e.g. by not setting the last error or returning undefined data.
+pub(crate) fn setparam(
+    cookie: &Cookie,
+    param: libc::c_int,
+    value: libc::size_t,
+) -> Result<(), ParamError> {
+    let value_ptr = &value as *const libc::size_t;
+    let ret = unsafe { magic_sys::magic_setparam(cookie.0, param, value_ptr as _) };
+    match ret {
+        -1 => Err(ParamError {
+            param,
+            errno: std::io::Error::last_os_error(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn getparam(cookie: &Cookie, param: libc::c_int) -> Result<libc::size_t, ParamError> {
+    let mut value: libc::size_t = 0;
+    let value_ptr = &mut value as *mut libc::size_t;
+    let ret = unsafe { magic_sys::magic_getparam(cookie.0, param, value_ptr as _) };
+    match ret {
+        -1 => Err(ParamError {
+            param,
+            errno: std::io::Error::last_os_error(),
+        }),
+        _ => Ok(value),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("could not access magic cookie parameter {}: {}", .param, .errno)]
+pub(crate) struct ParamError {
+    param: libc::c_int,
+    errno: std::io::Error,
+}
+
+impl ParamError {
+    pub fn errno(&self) -> &std::io::Error {
+        &self.errno
+    }
+
+    /// `magic_setparam()` and `magic_getparam()` only fail for unknown parameters
+    pub fn kind(&self) -> crate::cookie::ErrorKind {
+        match self.errno.kind() {
+            std::io::ErrorKind::InvalidInput => crate::cookie::ErrorKind::Unknown,
+            _ => crate::cookie::ErrorKind::Io,
+        }
+    }
+}
+
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error or returning undefined data, are reported as a [`CookieError`].
 pub(crate) fn check(cookie: &Cookie, filename: Option<&std::ffi::CStr>) -> Result<(), CookieError> {
     let filename_ptr = filename.map_or_else(std::ptr::null, std::ffi::CStr::as_ptr);
     let res = unsafe { magic_sys::magic_check(cookie.0, filename_ptr) };
@@ -152,16 +276,14 @@ pub(crate) fn check(cookie: &Cookie, filename: Option<&std::ffi::CStr>) -> Resul
             cookie,
             "`magic_check()` did not set last error".to_string(),
         )),
-        res => api_violation(
+        res => Err(api_violation(
             cookie,
             format!("expected 0 or -1 but `magic_check()` returned {}", res),
-        ),
+        )),
     }
 }
 
-/// # Panics
-///
-/// Panics if `magic_sys` violates its API contract, e.g. by not setting the last error or returning undefined data.
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error or returning undefined data, are reported as a [`CookieError`].
 pub(crate) fn compile(
     cookie: &Cookie,
     filename: Option<&std::ffi::CStr>,
@@ -175,16 +297,65 @@ pub(crate) fn compile(
             cookie,
             "`magic_compile()` did not set last error".to_string(),
         )),
-        res => api_violation(
+        res => Err(api_violation(
             cookie,
             format!("Expected 0 or -1 but `magic_compile()` returned {}", res),
-        ),
+        )),
     }
 }
 
-/// # Panics
+/// Like [`compile()`], but `libmagic` writes the compiled database into `dir` instead of the working directory
 ///
-/// Panics if `magic_sys` violates its API contract, e.g. by not setting the last error or returning undefined data.
+/// On Linux the call runs on a helper thread with a working directory of its own.
+/// Elsewhere the working directory of the whole process is switched for the duration of the call,
+/// which other threads observe, so callers must document that hazard.
+pub(crate) fn compile_in(
+    cookie: &Cookie,
+    filename: Option<&std::ffi::CStr>,
+    dir: &std::path::Path,
+) -> std::io::Result<Result<(), CookieError>> {
+    #[cfg(target_os = "linux")]
+    {
+        struct Borrowed<'a>(&'a Cookie);
+        // the calling thread waits for the helper, so the cookie is still used by one thread at a time
+        unsafe impl Send for Borrowed<'_> {}
+
+        let borrowed = Borrowed(cookie);
+        let helper = move || {
+            let borrowed = borrowed;
+            if unsafe { libc::unshare(libc::CLONE_FS) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            std::env::set_current_dir(dir)?;
+            Ok(compile(borrowed.0, filename))
+        };
+
+        std::thread::scope(|scope| match scope.spawn(helper).join() {
+            Ok(res) => res,
+            Err(panic) => std::panic::resume_unwind(panic),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        static WORKDIR_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+        struct Restore(std::path::PathBuf);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let _ = std::env::set_current_dir(&self.0);
+            }
+        }
+
+        let _lock = WORKDIR_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let _restore = Restore(std::env::current_dir()?);
+        std::env::set_current_dir(dir)?;
+        Ok(compile(cookie, filename))
+    }
+}
+
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error or returning undefined data, are reported as a [`CookieError`].
 pub(crate) fn list(cookie: &Cookie, filename: Option<&std::ffi::CStr>) -> Result<(), CookieError> {
     let filename_ptr = filename.map_or_else(std::ptr::null, std::ffi::CStr::as_ptr);
     let res = unsafe { magic_sys::magic_list(cookie.0, filename_ptr) };
@@ -195,16 +366,14 @@ pub(crate) fn list(cookie: &Cookie, filename: Option<&std::ffi::CStr>) -> Result
             cookie,
             "`magic_list()` did not set last error".to_string(),
         )),
-        res => api_violation(
+        res => Err(api_violation(
             cookie,
             format!("Expected 0 or -1 but `magic_list()` returned {}", res),
-        ),
+        )),
     }
 }
 
-/// # Panics
-///
-/// Panics if `magic_sys` violates its API contract, e.g. by not setting the last error or returning undefined data.
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error or returning undefined data, are reported as a [`CookieError`].
 pub(crate) fn load(cookie: &Cookie, filename: Option<&std::ffi::CStr>) -> Result<(), CookieError> {
     let filename_ptr = filename.map_or_else(std::ptr::null, std::ffi::CStr::as_ptr);
     let res = unsafe { magic_sys::magic_load(cookie.0, filename_ptr) };
@@ -215,16 +384,14 @@ pub(crate) fn load(cookie: &Cookie, filename: Option<&std::ffi::CStr>) -> Result
             cookie,
             "`magic_load()` did not set last error".to_string(),
         )),
-        res => api_violation(
+        res => Err(api_violation(
             cookie,
             format!("Expected 0 or -1 but `magic_load()` returned {}", res),
-        ),
+        )),
     }
 }
 
-/// # Panics
-///
-/// Panics if `magic_sys` violates its API contract, e.g. by not setting the last error or returning undefined data.
+/// Violations of the `magic_sys` API contract, e.g. not setting the last error or returning undefined data, are reported as a [`CookieError`].
 pub(crate) fn load_buffers(cookie: &Cookie, buffers: &[&[u8]]) -> Result<(), CookieError> {
     let mut ffi_buffers: Vec<*const u8> = Vec::with_capacity(buffers.len());
     let mut ffi_sizes: Vec<libc::size_t> = Vec::with_capacity(buffers.len());
@@ -248,13 +415,13 @@ pub(crate) fn load_buffers(cookie: &Cookie, buffers: &[&[u8]]) -> Result<(), Coo
             cookie,
             "`magic_load_buffers()` did not set last error".to_string(),
         )),
-        res => api_violation(
+        res => Err(api_violation(
             cookie,
             format!(
                 "Expected 0 or -1 but `magic_load_buffers()` returned {}",
                 res
             ),
-        ),
+        )),
     }
 }
 
@@ -286,6 +453,21 @@ impl OpenError {
     }
 }
 
+pub(crate) fn getpath(
+    filename: Option<&std::ffi::CStr>,
+    action: libc::c_int,
+) -> Option<std::ffi::CString> {
+    let filename_ptr = filename.map_or_else(std::ptr::null, std::ffi::CStr::as_ptr);
+    let res = unsafe { magic_sys::magic_getpath(filename_ptr, action) };
+
+    if res.is_null() {
+        None
+    } else {
+        let c_str = unsafe { std::ffi::CStr::from_ptr(res) };
+        Some(c_str.into())
+    }
+}
+
 pub(crate) fn version() -> libc::c_int {
     unsafe { magic_sys::magic_version() }
 }
@@ -0,0 +1,273 @@
+//! Pool of loaded [`Cookie`]s shared between threads
+//!
+//! A single `magic_t` must not be used by several threads at once,
+//! so [`CookiePool`] hands out each loaded cookie to one thread at a time.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use crate::cookie::{self, DatabaseBuffer, DatabasePaths, DiagnosticsMode, Flags, Limits, Load};
+use crate::Cookie;
+
+/// Where the cookies of a [`CookiePool`] load their database from
+#[derive(Clone)]
+enum Database {
+    Paths(DatabasePaths),
+    Buffers(Vec<DatabaseBuffer>),
+}
+
+struct State {
+    idle: Vec<Cookie<Load>>,
+    /// Number of cookies alive, idle or handed out
+    size: usize,
+}
+
+/// Thread-safe pool of [`Cookie`]s loaded from the same database
+///
+/// Cookies are created on demand up to `max_size`, further [`get()`](CookiePool::get) calls block until one is returned.
+/// Every cookie is opened with the same [`Flags`]. Flags, [`Limits`] and diagnostics settings a borrower changes
+/// are reset when the cookie is returned to the pool, a cookie that cannot be reset is closed instead.
+pub struct CookiePool {
+    state: Mutex<State>,
+    returned: Condvar,
+    flags: Flags,
+    /// Limits of a freshly loaded cookie
+    limits: Limits,
+    max_size: usize,
+    database: Database,
+}
+
+impl CookiePool {
+    /// Creates a pool of cookies loading the database files in `paths`
+    ///
+    /// One cookie is loaded right away so that an unusable database is reported here.
+    pub fn with_paths(
+        flags: Flags,
+        paths: DatabasePaths,
+        max_size: usize,
+    ) -> Result<CookiePool, PoolError> {
+        Self::new(flags, Database::Paths(paths), max_size)
+    }
+
+    /// Creates a pool of cookies sharing the compiled database `buffers`
+    ///
+    /// Every cookie shares the same buffers without copying them.
+    /// One cookie is loaded right away so that an unusable database is reported here.
+    pub fn with_buffers<I, B>(
+        flags: Flags,
+        buffers: I,
+        max_size: usize,
+    ) -> Result<CookiePool, PoolError>
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<DatabaseBuffer>,
+    {
+        let buffers = buffers.into_iter().map(Into::into).collect();
+        Self::new(flags, Database::Buffers(buffers), max_size)
+    }
+
+    fn new(flags: Flags, database: Database, max_size: usize) -> Result<CookiePool, PoolError> {
+        let mut pool = CookiePool {
+            state: Mutex::new(State {
+                idle: Vec::new(),
+                size: 0,
+            }),
+            returned: Condvar::new(),
+            flags,
+            limits: Limits::default(),
+            max_size: max_size.max(1),
+            database,
+        };
+
+        let cookie = pool.create()?;
+        pool.limits = cookie.limits()?;
+        let mut state = pool.lock();
+        state.idle.push(cookie);
+        state.size += 1;
+        drop(state);
+
+        Ok(pool)
+    }
+
+    /// Takes a cookie out of the pool, waiting for one to be returned if `max_size` cookies are in use
+    pub fn get(&self) -> Result<PooledCookie<'_>, PoolError> {
+        let mut state = self.lock();
+        loop {
+            if let Some(cookie) = state.idle.pop() {
+                return Ok(self.guard(cookie));
+            }
+            if state.size < self.max_size {
+                return self.grow(state);
+            }
+            state = self
+                .returned
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    /// Takes a cookie out of the pool if one is idle or can be created without waiting
+    pub fn try_get(&self) -> Result<Option<PooledCookie<'_>>, PoolError> {
+        let mut state = self.lock();
+        if let Some(cookie) = state.idle.pop() {
+            return Ok(Some(self.guard(cookie)));
+        }
+        match state.size < self.max_size {
+            true => self.grow(state).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Closes idle cookies until at most `idle` of them are left
+    pub fn shrink_to(&self, idle: usize) {
+        let mut state = self.lock();
+        let keep = state.idle.len().min(idle);
+        let closed = state.idle.split_off(keep);
+        state.size -= closed.len();
+        drop(state);
+        drop(closed);
+    }
+
+    /// Number of cookies currently alive, idle or in use
+    pub fn size(&self) -> usize {
+        self.lock().size
+    }
+
+    /// Number of cookies currently waiting in the pool
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    /// Maximum number of cookies alive at the same time
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Flags every cookie of this pool is opened with
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Database paths the cookies are loaded from, `None` if they share buffers
+    pub(crate) fn paths(&self) -> Option<&DatabasePaths> {
+        match &self.database {
+            Database::Paths(paths) => Some(paths),
+            Database::Buffers(_) => None,
+        }
+    }
+
+    /// Creates a new pool like this one, loading the database anew
+    pub(crate) fn renew(&self) -> Result<CookiePool, PoolError> {
+        Self::new(self.flags, self.database.clone(), self.max_size)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // the state is consistent at any point, so a panic elsewhere does not poison it for us
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Reserves a slot for a new cookie and loads it without holding the lock
+    fn grow(&self, mut state: MutexGuard<'_, State>) -> Result<PooledCookie<'_>, PoolError> {
+        state.size += 1;
+        drop(state);
+
+        match self.create() {
+            Ok(cookie) => Ok(self.guard(cookie)),
+            Err(err) => {
+                self.lock().size -= 1;
+                self.returned.notify_one();
+                Err(err)
+            }
+        }
+    }
+
+    fn create(&self) -> Result<Cookie<Load>, PoolError> {
+        let cookie = Cookie::open(self.flags)?;
+        let cookie = match &self.database {
+            Database::Paths(paths) => cookie.load(paths)?,
+            Database::Buffers(buffers) => cookie.load_buffers(buffers.iter().cloned())?,
+        };
+        Ok(cookie)
+    }
+
+    fn guard(&self, cookie: Cookie<Load>) -> PooledCookie<'_> {
+        PooledCookie {
+            pool: self,
+            cookie: Some(cookie),
+        }
+    }
+}
+
+/// Cookie taken out of a [`CookiePool`], returned to it on drop
+pub struct PooledCookie<'pool> {
+    pool: &'pool CookiePool,
+    cookie: Option<Cookie<Load>>,
+}
+
+impl std::ops::Deref for PooledCookie<'_> {
+    type Target = Cookie<Load>;
+
+    fn deref(&self) -> &Cookie<Load> {
+        self.cookie.as_ref().expect("cookie is only taken on drop")
+    }
+}
+
+impl Drop for PooledCookie<'_> {
+    fn drop(&mut self) {
+        if let Some(cookie) = self.cookie.take() {
+            cookie.set_diagnostics(DiagnosticsMode::default());
+            drop(cookie.take_diagnostics());
+            let reset = cookie.set_flags(self.pool.flags).is_ok()
+                && cookie.set_limits(&self.pool.limits).is_ok();
+
+            let mut state = self.pool.lock();
+            match reset {
+                true => state.idle.push(cookie),
+                _ => state.size -= 1,
+            }
+            drop(state);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+/// Error within [`CookiePool`] functions that create a new cookie
+#[derive(thiserror::Error, Debug)]
+pub enum PoolError {
+    #[error(transparent)]
+    Open(#[from] cookie::OpenError),
+    #[error(transparent)]
+    Load(#[from] cookie::LoadError<cookie::Open>),
+    #[error(transparent)]
+    Limits(#[from] cookie::LimitsError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pool sharing a database compiled from a single magic entry
+    fn pool() -> CookiePool {
+        let compiled = cookie::tests::compiled("0\tstring\tPOOLTEST\tpool test data\n");
+        CookiePool::with_buffers(Flags::empty(), [compiled], 1).unwrap()
+    }
+
+    #[test]
+    fn returned_cookies_are_reset() {
+        let pool = pool();
+        let defaults = pool.get().unwrap().limits().unwrap();
+
+        let cookie = pool.get().unwrap();
+        cookie.set_limits(&Limits::hardened()).unwrap();
+        cookie.set_flags(Flags::MIME_TYPE).unwrap();
+        cookie.set_diagnostics(DiagnosticsMode::Collect);
+        drop(cookie);
+
+        // the pool holds a single cookie, so this is the one changed above
+        let cookie = pool.get().unwrap();
+        assert_eq!(pool.size(), 1);
+        assert_eq!(cookie.limits().unwrap(), defaults);
+        assert_eq!(cookie.flags(), Flags::empty());
+        assert_eq!(cookie.diagnostics(), DiagnosticsMode::Inherit);
+        assert_eq!(cookie.buffer(b"POOLTEST").unwrap(), "pool test data");
+    }
+}
@@ -0,0 +1,296 @@
+//! Swapping the database of cookies in use without interrupting identifications
+//!
+//! [`ReloadableMagic`] holds the current [`CookiePool`] behind an [`Arc`].
+//! A reload loads a complete new pool first and only then replaces the current one,
+//! so identifications that already took a cookie finish on the old database.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::cookie::{DatabaseBuffer, DatabasePaths, Load};
+use crate::pool::{CookiePool, PoolError};
+use crate::Cookie;
+
+/// Handle to a [`CookiePool`] whose database can be replaced at any time
+///
+/// A failed reload leaves the current database in place.
+pub struct ReloadableMagic {
+    current: RwLock<Arc<CookiePool>>,
+    /// Serializes reloads, so that a slow one does not replace the result of a later one
+    reloading: Mutex<()>,
+}
+
+impl ReloadableMagic {
+    /// Creates a handle starting out with the cookies of `pool`
+    pub fn new(pool: CookiePool) -> ReloadableMagic {
+        ReloadableMagic {
+            current: RwLock::new(Arc::new(pool)),
+            reloading: Mutex::new(()),
+        }
+    }
+
+    /// Returns the current pool
+    ///
+    /// The pool stays usable after a reload, its cookies keep the database they were loaded with.
+    pub fn pool(&self) -> Arc<CookiePool> {
+        self.current
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Runs `f` with a cookie of the current pool, see [`CookiePool::get()`]
+    pub fn with<T>(&self, f: impl FnOnce(&Cookie<Load>) -> T) -> Result<T, PoolError> {
+        let pool = self.pool();
+        let cookie = pool.get()?;
+        Ok(f(&cookie))
+    }
+
+    /// Loads the database of the current pool anew, e.g. after its files changed
+    pub fn reload(&self) -> Result<(), PoolError> {
+        let _reloading = self.lock_reloading();
+        let pool = self.pool().renew()?;
+        self.swap(pool);
+        Ok(())
+    }
+
+    /// Switches to the database files in `paths`, keeping flags and size of the current pool
+    pub fn reload_paths(&self, paths: DatabasePaths) -> Result<(), PoolError> {
+        let _reloading = self.lock_reloading();
+        let current = self.pool();
+        let pool = CookiePool::with_paths(current.flags(), paths, current.max_size())?;
+        self.swap(pool);
+        Ok(())
+    }
+
+    /// Switches to the compiled database `buffers`, keeping flags and size of the current pool
+    pub fn reload_buffers<I, B>(&self, buffers: I) -> Result<(), PoolError>
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<DatabaseBuffer>,
+    {
+        let _reloading = self.lock_reloading();
+        let current = self.pool();
+        let pool = CookiePool::with_buffers(current.flags(), buffers, current.max_size())?;
+        self.swap(pool);
+        Ok(())
+    }
+
+    /// Replaces the current pool with `pool`, returning the previous one
+    pub fn swap(&self, pool: CookiePool) -> Arc<CookiePool> {
+        let mut current = self.current.write().unwrap_or_else(|err| err.into_inner());
+        std::mem::replace(&mut current, Arc::new(pool))
+    }
+
+    fn lock_reloading(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.reloading.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use self::watch::Watcher;
+
+#[cfg(target_os = "linux")]
+mod watch {
+    use std::ffi::OsString;
+    use std::os::fd::OwnedFd;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::ReloadableMagic;
+    use crate::cookie::DatabaseAction;
+    use crate::magic::inotify::{self, Inotify, Wait};
+
+    /// How long the files have to stay untouched after a change before they are reloaded
+    const SETTLE: std::time::Duration = std::time::Duration::from_millis(250);
+
+    const MASK: u32 = libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO;
+
+    impl ReloadableMagic {
+        /// Reloads the database whenever its files change, until the returned [`Watcher`] is dropped
+        ///
+        /// The files of the current pool are watched with inotify,
+        /// switching to other files with [`ReloadableMagic::reload_paths()`] requires a new watcher.
+        /// Failed reloads are logged and keep the current database.
+        pub fn watch(self: &Arc<Self>) -> std::io::Result<Watcher> {
+            let paths = match self.pool().paths() {
+                Some(paths) => paths.resolve(DatabaseAction::Load),
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "the database was loaded from buffers, there are no files to watch",
+                    ))
+                }
+            };
+
+            let inotify = Inotify::new()?;
+            let mut watches = Vec::new();
+            for path in &paths {
+                if path.is_dir() {
+                    watches.push((inotify.watch(path, MASK)?, None));
+                }
+                // files are often replaced by renaming, which only shows up on the directory
+                let dir = match path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                if let Some(name) = path.file_name() {
+                    let mut compiled = name.to_owned();
+                    compiled.push(".mgc");
+                    watches.push((
+                        inotify.watch(dir, MASK)?,
+                        Some(vec![name.to_owned(), compiled]),
+                    ));
+                }
+            }
+
+            let (stop, stopper) = inotify::pipe()?;
+            let magic = self.clone();
+            let thread = std::thread::Builder::new()
+                .name("libmagic-watch".to_string())
+                .spawn(move || run(&magic, &inotify, &watches, &stop))?;
+
+            Ok(Watcher {
+                stopper: Some(stopper),
+                thread: Some(thread),
+            })
+        }
+    }
+
+    /// Watch descriptor and the names within it that matter, `None` meaning any
+    type Watches = [(libc::c_int, Option<Vec<OsString>>)];
+
+    fn run(magic: &ReloadableMagic, inotify: &Inotify, watches: &Watches, stop: &OwnedFd) {
+        let mut changed = false;
+        loop {
+            let timeout = changed.then_some(SETTLE);
+            match inotify.wait(stop, timeout) {
+                Ok(Wait::Events(events)) => {
+                    changed |= events.iter().any(|(wd, name)| relevant(watches, *wd, name));
+                }
+                Ok(Wait::Timeout) => {
+                    changed = false;
+                    match magic.reload() {
+                        Ok(_) => log::info!("reloaded magic database after its files changed"),
+                        Err(err) => log::warn!("could not reload changed magic database: {}", err),
+                    }
+                }
+                Ok(Wait::Stopped) => return,
+                Err(err) => {
+                    log::error!("stopped watching magic database files: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn relevant(watches: &Watches, wd: libc::c_int, name: &Option<OsString>) -> bool {
+        watches
+            .iter()
+            .filter(|(watched, _)| *watched == wd)
+            .any(|(_, names)| match (names, name) {
+                (None, _) => true,
+                (Some(names), Some(name)) => names.contains(name),
+                (Some(_), None) => false,
+            })
+    }
+
+    /// Background thread reloading a [`ReloadableMagic`], stopped on drop
+    pub struct Watcher {
+        /// Closing this wakes up the thread
+        stopper: Option<OwnedFd>,
+        thread: Option<std::thread::JoinHandle<()>>,
+    }
+
+    impl Drop for Watcher {
+        fn drop(&mut self) {
+            drop(self.stopper.take());
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::{self, Flags};
+
+    fn compiled(test: &str) -> Vec<u8> {
+        cookie::tests::compiled(&format!("0\tstring\t{}\t{} data\n", test, test))
+    }
+
+    fn magic() -> ReloadableMagic {
+        ReloadableMagic::new(
+            CookiePool::with_buffers(Flags::empty(), [compiled("OLDTEST")], 1).unwrap(),
+        )
+    }
+
+    #[test]
+    fn reloading_buffers_swaps_the_database() {
+        let magic = magic();
+        assert_eq!(
+            magic
+                .with(|cookie| cookie.buffer(b"OLDTEST"))
+                .unwrap()
+                .unwrap(),
+            "OLDTEST data"
+        );
+
+        magic.reload_buffers([compiled("NEWTEST")]).unwrap();
+        assert_eq!(
+            magic
+                .with(|cookie| cookie.buffer(b"NEWTEST"))
+                .unwrap()
+                .unwrap(),
+            "NEWTEST data"
+        );
+        assert_ne!(
+            magic
+                .with(|cookie| cookie.buffer(b"OLDTEST"))
+                .unwrap()
+                .unwrap(),
+            "OLDTEST data"
+        );
+        assert_eq!(magic.pool().max_size(), 1);
+    }
+
+    #[test]
+    fn checked_out_cookies_keep_the_old_database() {
+        let magic = magic();
+        let old = magic.pool();
+        let cookie = old.get().unwrap();
+
+        magic.reload_buffers([compiled("NEWTEST")]).unwrap();
+        assert_eq!(cookie.buffer(b"OLDTEST").unwrap(), "OLDTEST data");
+        assert_eq!(
+            magic
+                .with(|cookie| cookie.buffer(b"NEWTEST"))
+                .unwrap()
+                .unwrap(),
+            "NEWTEST data"
+        );
+    }
+
+    #[test]
+    fn failed_reloads_keep_the_current_pool() {
+        let magic = magic();
+        let current = magic.pool();
+
+        assert!(magic
+            .reload_buffers([b"not a magic database".to_vec()])
+            .is_err());
+        assert!(Arc::ptr_eq(&current, &magic.pool()));
+        assert_eq!(
+            magic
+                .with(|cookie| cookie.buffer(b"OLDTEST"))
+                .unwrap()
+                .unwrap(),
+            "OLDTEST data"
+        );
+    }
+}